@@ -24,9 +24,11 @@ use shellwidget::ShellWidget;
 
 
 fn build_ui(application: &gtk::Application) {
+    let mut session = tp::Session::new(Command::new("zsh"), None).unwrap();    // XXX
+
     let window = gtk::ApplicationWindow::new(application);
 
-    window.set_title("Hello, World!");
+    window.set_title(session.title());
     window.set_border_width(10);
     window.set_position(gtk::WindowPosition::Center);
     window.set_default_size(1000, 500);
@@ -40,10 +42,15 @@ fn build_ui(application: &gtk::Application) {
     let container = gtk::Box::new(gtk::Orientation::Vertical, 5);
 
     let label = gtk::Label::new(None);
-    label.set_text("Hello, World!");
+    label.set_text(session.tab_title());
     container.add(&label);
 
-    let session = tp::Session::new(Command::new("zsh")).unwrap();    // XXX
+    let (window_, label_) = (window.clone(), label.clone());
+    session.set_title_hook(Some(Box::new(move |title, tab_title| {
+        window_.set_title(title);
+        label_.set_text(tab_title);
+    })));
+
     let shell = ShellWidget::new(session);
     window.add(&shell.main_widget());
 
@@ -1,4 +1,4 @@
-use ::term::VTColor;
+use ::term::{Style, VTColor, VTPaletteSlot};
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -13,18 +13,71 @@ impl Default for Rgba {
 }
 
 
-#[derive(Debug)]
-pub struct ColorScheme {
+#[derive(Debug, Clone)]
+struct Palette {
     fg: Rgba,
     bg: Rgba,
+    cursor: Rgba,
     system: [Rgba ; 16],
+    /// Colors 16 - 255, ie. the 6 level RGB cube and the grayscale
+    extended: [Rgba ; 240],
 }
 
-impl Default for ColorScheme {
-    fn default() -> ColorScheme {
-        ColorScheme {
+impl Palette {
+    fn extended_color(idx: u8) -> Rgba {
+        const CUBE: [u8 ; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+
+        match idx {
+            // 6 level RGB
+            16 ... 231 => {
+                let i = idx - 16;
+                let b = i % 6;
+                let g = i / 6 % 6;
+                let r = i / 36;
+                Rgba::rgb(CUBE[r as usize], CUBE[g as usize], CUBE[b as usize])
+            },
+            // Grayscale
+            232 ... 255 => {
+                let gray = (idx - 232) * 10 + 8;
+                Rgba::rgb(gray, gray, gray)
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn get(&self, slot: VTPaletteSlot) -> Rgba {
+        match slot {
+            VTPaletteSlot::Indexed(idx @ 0 ... 15) => self.system[idx as usize],
+            VTPaletteSlot::Indexed(idx) => self.extended[idx as usize - 16],
+            VTPaletteSlot::Foreground => self.fg,
+            VTPaletteSlot::Background => self.bg,
+            VTPaletteSlot::Cursor => self.cursor,
+        }
+    }
+
+    fn get_mut(&mut self, slot: VTPaletteSlot) -> &mut Rgba {
+        match slot {
+            VTPaletteSlot::Indexed(idx @ 0 ... 15) => &mut self.system[idx as usize],
+            VTPaletteSlot::Indexed(idx) => &mut self.extended[idx as usize - 16],
+            VTPaletteSlot::Foreground => &mut self.fg,
+            VTPaletteSlot::Background => &mut self.bg,
+            VTPaletteSlot::Cursor => &mut self.cursor,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        let mut extended = [Rgba::default() ; 240];
+        for (i, color) in extended.iter_mut().enumerate() {
+            *color = Palette::extended_color(i as u8 + 16);
+        }
+
+        Palette {
             fg: Rgba::rgb(0xff, 0xff, 0xff),
             bg: Rgba::default(),
+            cursor: Rgba::rgb(0xff, 0xff, 0xff),
+            extended,
             system: [
                 // Base colors
                 Rgba::rgb(0x00, 0x00, 0x00),
@@ -49,38 +102,103 @@ impl Default for ColorScheme {
     }
 }
 
-impl ColorScheme {
-    fn indexed_color(&self, idx: u8) -> Rgba {
-        const CUBE: [u8 ; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
 
-        match idx {
-            // System palette
-            0 ... 15 => self.system[idx as usize],
-            // 6 level RGB
-            16 ... 231 => {
-                let i = idx - 16;
-                let b = i % 6;
-                let g = i / 6 % 6;
-                let r = i / 36;
-                Rgba::rgb(CUBE[r as usize], CUBE[g as usize], CUBE[b as usize])
-            },
-            // Grayscale
-            232 ... 255 => {
-                let gray = (idx - 232) * 10 + 8;
-                Rgba::rgb(gray, gray, gray)
-            },
-            _ => unreachable!(),
+/// Color scheme of a session
+///
+/// Colors may be changed at runtime by the program running in the session (see `PaletteRequest`),
+/// the scheme remembers the colors it was constructed with so that they can be restored.
+#[derive(Debug, Clone)]
+pub struct ColorScheme {
+    current: Palette,
+    defaults: Palette,
+}
+
+impl Default for ColorScheme {
+    fn default() -> ColorScheme {
+        ColorScheme {
+            current: Palette::default(),
+            defaults: Palette::default(),
         }
     }
+}
 
+impl ColorScheme {
     pub fn get_color(&self, vtcolor: VTColor) -> Rgba {
         use self::VTColor::*;
 
         match vtcolor {
-            DefaultFg => self.fg,
-            DefaultBg => self.bg,
-            Indexed(idx) => self.indexed_color(idx),
+            DefaultFg => self.current.fg,
+            DefaultBg => self.current.bg,
+            Indexed(idx) => self.current.get(VTPaletteSlot::Indexed(idx)),
             Rgb(r, g, b) => Rgba(r, g, b, 0xff),
         }
     }
+
+    /// Color of underlines drawn for `style`, the foreground color unless an underline color is set
+    pub fn underline_color(&self, style: &Style) -> Rgba {
+        self.get_color(style.col_ul.unwrap_or(style.col_fg))
+    }
+
+    pub fn cursor_color(&self) -> Rgba { self.current.cursor }
+
+    /// Get the current color of a palette slot
+    pub fn get_slot(&self, slot: VTPaletteSlot) -> Rgba {
+        self.current.get(slot)
+    }
+
+    pub fn set_slot(&mut self, slot: VTPaletteSlot, color: Rgba) {
+        *self.current.get_mut(slot) = color;
+    }
+
+    /// Reset a palette slot to its default color, `None` resets all indexed colors
+    pub fn reset_slot(&mut self, slot: Option<VTPaletteSlot>) {
+        match slot {
+            Some(slot) => *self.current.get_mut(slot) = self.defaults.get(slot),
+            None => {
+                self.current.system = self.defaults.system;
+                self.current.extended = self.defaults.extended;
+            },
+        }
+    }
+
+    /// Reset all colors to defaults
+    pub fn reset(&mut self) {
+        self.current = self.defaults.clone();
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+use super::*;
+
+#[test]
+fn colorscheme_set_reset() {
+    let mut colors = ColorScheme::default();
+    let red = Rgba::rgb(0xff, 0, 0);
+
+    colors.set_slot(VTPaletteSlot::Indexed(200), red);
+    colors.set_slot(VTPaletteSlot::Background, red);
+    assert_eq!(colors.get_color(VTColor::Indexed(200)), red);
+    assert_eq!(colors.get_color(VTColor::DefaultBg), red);
+
+    colors.reset_slot(None);
+    assert_eq!(colors.get_color(VTColor::Indexed(200)), Rgba::rgb(0xff, 0x00, 0xd7));
+    assert_eq!(colors.get_color(VTColor::DefaultBg), red);
+
+    colors.reset();
+    assert_eq!(colors.get_color(VTColor::DefaultBg), Rgba::default());
+}
+
+#[test]
+fn colorscheme_underline() {
+    let colors = ColorScheme::default();
+    let mut style = Style::with_fg(VTColor::Indexed(1));
+    assert_eq!(colors.underline_color(&style), colors.get_color(VTColor::Indexed(1)));
+
+    style.col_ul = Some(VTColor::Rgb(1, 2, 3));
+    assert_eq!(colors.underline_color(&style), Rgba::rgb(1, 2, 3));
+}
+
 }
@@ -7,6 +7,7 @@ extern crate tp_term;
 use std::fmt;
 use std::io::{self, Read, Write};
 use std::thread;
+use std::path::Path;
 use std::process::Command;
 use std::os::unix::io::{RawFd, AsRawFd};
 
@@ -14,7 +15,7 @@ pub mod term { pub use tp_term::*; }
 pub mod pty { pub use tp_pty::*; }
 
 use pty::Process;
-use term::{Term, InputData};
+use term::{Term, InputData, VTMode, VTSelection, PaletteRequest, ClipboardRequest};
 
 mod colors;
 pub use colors::*;
@@ -34,20 +35,43 @@ mod err {
 pub use err::*;
 
 
+/// Title used when the program running in the session hasn't set any
+pub const DEFAULT_TITLE: &str = "teepee";
+
+
+/// Called before pasting text containing line breaks into a program that doesn't use bracketed paste,
+/// the text is only pasted if it returns `true`
+pub type PasteHook = Box<dyn FnMut(&str) -> bool>;
+
+/// Called with the window title and the tab title (see `Session::title()` and `Session::tab_title()`)
+/// whenever the program running in the session changes them
+pub type TitleHook = Box<dyn FnMut(&str, &str)>;
+
 pub struct Session {
     ps: Process,
     buffer: Vec<u8>,
     pub term: Term,
     pub colors: ColorScheme,
+    paste_hook: Option<PasteHook>,
+    title_hook: Option<TitleHook>,
 }
 
 impl Session {
-    pub fn new(program: Command) -> Result<Session> {
+    /// Spawns `program` in a new session. If `working_dir` is specified and exists,
+    /// the program is started there, this is useful for opening a session in the directory
+    /// of another session (see `working_dir()`).
+    pub fn new(mut program: Command, working_dir: Option<&Path>) -> Result<Session> {
+        if let Some(dir) = working_dir.filter(|dir| dir.is_dir()) {
+            program.current_dir(dir);
+        }
+
         Ok(Session {
             ps: Process::new(program)?,
             buffer: vec![0; 4096],
             term: Term::new(),
             colors: ColorScheme::default(),
+            paste_hook: None,
+            title_hook: None,
         })
     }
 
@@ -57,6 +81,8 @@ impl Session {
             let actually_read = self.ps.read(&mut self.buffer)?;
             if actually_read > 0 {
                 self.term.write(&self.buffer[0..actually_read]);
+                self.handle_palette_requests()?;
+                self.handle_title_changes();
             }
             Ok(actually_read)
         } else {
@@ -64,15 +90,105 @@ impl Session {
         }
     }
 
+    /// Apply palette changes requested by the program and answer its palette queries
+    fn handle_palette_requests(&mut self) -> Result<()> {
+        let requests: Vec<_> = self.term.reset_palette_requests().collect();
+        for request in requests {
+            match request {
+                PaletteRequest::Set(slot, (r, g, b)) => self.colors.set_slot(slot, Rgba::rgb(r, g, b)),
+                PaletteRequest::Reset(slot) => self.colors.reset_slot(slot),
+                PaletteRequest::Query(slot) => {
+                    let Rgba(r, g, b, _) = self.colors.get_slot(slot);
+                    let size = self.term.color_answer(slot, (r, g, b), &mut self.buffer).expect("Answer buffer not large enough");
+                    self.ps.write(&self.buffer[0..size])?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_title_changes(&mut self) {
+        if self.term.reset_title_changed() {
+            if let Some(mut hook) = self.title_hook.take() {
+                hook(self.title(), self.tab_title());
+                self.title_hook = Some(hook);
+            }
+        }
+    }
+
     pub fn input(&mut self, input: InputData) -> Result<usize> {
         if let InputData::Str(string) = input {
             self.ps.write(string.as_bytes())
+        } else if let InputData::Paste(text) = input {
+            // Without bracketed paste, each line would run as a command in a shell
+            let multiline = text.contains(|ch| ch == '\n' || ch == '\r');
+            if multiline && !self.term.mode().contains(VTMode::BRACKETED_PASTE) {
+                if let Some(ref mut hook) = self.paste_hook {
+                    if !hook(text) {
+                        return Ok(0);
+                    }
+                }
+            }
+
+            let mut buffer = vec![0; text.len() + 12];
+            let size = self.term.input(input, &mut buffer).expect("Paste buffer not large enough");
+            self.ps.write(&buffer[0..size])
         } else {
             let size = self.term.input(input, &mut self.buffer).expect("Input buffer not large enough");
             self.ps.write(&self.buffer[0..size])
         }.map_err(io::Error::into)
     }
 
+    /// Set a hook to confirm multi-line pastes that the program can't tell apart from typed input
+    pub fn set_paste_hook(&mut self, hook: Option<PasteHook>) {
+        self.paste_hook = hook;
+    }
+
+    /// Set a hook to update the window and tab headers when the titles change
+    pub fn set_title_hook(&mut self, hook: Option<TitleHook>) {
+        self.title_hook = hook;
+    }
+
+    /// Answer a clipboard read request (`VTReport::Clipboard`) with the selection content
+    pub fn clipboard_answer(&mut self, selection: VTSelection, data: &str) -> Result<usize> {
+        let mut buffer = vec![0; data.len() * 4 / 3 + 16];
+        let size = self.term.clipboard_answer(selection, data, &mut buffer).expect("Answer buffer not large enough");
+        self.ps.write(&buffer[0..size]).map_err(io::Error::into)
+    }
+
+    /// Clipboard requests awaiting the user's confirmation as the clipboard policy is `Permission::Ask`
+    pub fn clipboard_pending(&self) -> &[ClipboardRequest] {
+        self.term.clipboard_pending()
+    }
+
+    /// Allow or deny the oldest clipboard request in `clipboard_pending()`
+    pub fn clipboard_confirm(&mut self, allow: bool) {
+        self.term.clipboard_confirm(allow);
+    }
+
+    /// Current working directory of the program as reported by the shell, if any.
+    /// Only directories that exist locally are reported, the shell could be running on a remote host.
+    pub fn working_dir(&self) -> Option<&Path> {
+        self.term.working_dir().filter(|dir| dir.is_dir())
+    }
+
+    /// Title for the window header, as set by the program running in the session
+    pub fn title(&self) -> &str {
+        match self.term.title() {
+            "" => DEFAULT_TITLE,
+            title => title,
+        }
+    }
+
+    /// Short title for tab headers, as set by the program running in the session
+    pub fn tab_title(&self) -> &str {
+        match self.term.icon_name() {
+            "" => DEFAULT_TITLE,
+            name => name,
+        }
+    }
+
     pub fn screen_resize(&mut self, cols: u16, rows: u16) -> Result<()> {
         self.ps.set_winsize(cols, rows)?;
         self.term.screen_resize(cols, rows);
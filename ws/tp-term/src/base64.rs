@@ -0,0 +1,86 @@
+//! Minimal base64 encoder & decoder for control string payloads
+
+const ALPHABET: &[u8 ; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn decode_byte(byte: u8) -> Option<u8> {
+    match byte {
+        b'A' ... b'Z' => Some(byte - b'A'),
+        b'a' ... b'z' => Some(byte - b'a' + 26),
+        b'0' ... b'9' => Some(byte - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decode base64 `data`, padding is optional. Returns `Err` on invalid input.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, ()> {
+    let data = match data.iter().position(|b| *b == b'=') {
+        Some(pad) if data[pad..].iter().all(|b| *b == b'=') && data.len().is_multiple_of(4) => &data[..pad],
+        Some(_) => return Err(()),
+        None => data,
+    };
+
+    let mut res = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut acc = 0u32;
+        for (i, byte) in chunk.iter().enumerate() {
+            acc |= (decode_byte(*byte).ok_or(())? as u32) << (18 - 6 * i);
+        }
+
+        match chunk.len() {
+            4 => res.extend(&[(acc >> 16) as u8, (acc >> 8) as u8, acc as u8]),
+            3 => res.extend(&[(acc >> 16) as u8, (acc >> 8) as u8]),
+            2 => res.push((acc >> 16) as u8),
+            _ => return Err(()),
+        }
+    }
+
+    Ok(res)
+}
+
+/// Encode `data` as base64, with padding
+pub fn encode(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let acc = chunk.iter().enumerate().fold(0u32, |acc, (i, byte)| acc | (*byte as u32) << (16 - 8 * i));
+
+        for i in 0 .. 4 {
+            if i <= chunk.len() {
+                res.push(ALPHABET[(acc >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+
+    res
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_roundtrip() {
+        let tests: &[(&[u8], &str)] = &[
+            (b"", ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foobar", "Zm9vYmFy"),
+        ];
+
+        for &(raw, encoded) in tests {
+            assert_eq!(encode(raw), encoded);
+            assert_eq!(decode(encoded.as_bytes()).unwrap(), raw);
+        }
+
+        assert_eq!(decode(b"Zm8").unwrap(), b"fo");
+        assert!(decode(b"Zm=8").is_err());
+        assert!(decode(b"Z").is_err());
+        assert!(decode(b"Zm9v!").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+/// Shell integration: command blocks
+///
+/// Shells that support the FinalTerm semantic prompt protocol (OSC 133) mark the start of the prompt,
+/// the start of the command line, the start of the command output and the end of the command.
+/// The marks are recorded on screen lines (see `LineMark`) and travel along with them into the scrollback.
+/// Command blocks are then reconstructed from the marks on demand.
+
+use std::time::{Duration, Instant};
+
+use ::screen::Screen;
+
+
+/// Position of a mark in the screen history, see `Screen::history_len()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockPos {
+    /// History line index, 0 being the oldest scrollback line
+    pub line: usize,
+    /// Column
+    pub x: u32,
+}
+
+/// A shell command along with its prompt and output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandBlock {
+    /// Start of the prompt
+    pub prompt: BlockPos,
+    /// Start of the command line
+    pub command: Option<BlockPos>,
+    /// Start of the output
+    pub output: Option<BlockPos>,
+    /// End of the block, `None` if the command is still running
+    pub end: Option<BlockPos>,
+    /// Exit code of the command, if the shell reported it
+    pub exit_code: Option<i32>,
+    /// Time between the start of the output and the end of the command
+    pub duration: Option<Duration>,
+}
+
+impl CommandBlock {
+    fn new(prompt: BlockPos) -> CommandBlock {
+        CommandBlock {
+            prompt,
+            command: None,
+            output: None,
+            end: None,
+            exit_code: None,
+            duration: None,
+        }
+    }
+}
+
+/// Reconstruct command blocks from marks in the screen history, oldest first
+pub fn command_blocks(screen: &Screen) -> Vec<CommandBlock> {
+    use VTSemanticMark::*;
+
+    let mut blocks: Vec<CommandBlock> = vec![];
+    let mut output_time: Option<Instant> = None;
+
+    for line in 0 .. screen.history_len() {
+        for mark in screen.history_marks(line) {
+            let pos = BlockPos { line, x: mark.x };
+
+            // A new block is started by a prompt, or by any other mark if there's no block in progress
+            let in_progress = blocks.last().is_some_and(|block| block.end.is_none());
+            let starts_block = match mark.mark {
+                PromptStart => true,
+                CommandFinished(_) => false,
+                _ => !in_progress,
+            };
+
+            if starts_block {
+                if let Some(block) = blocks.last_mut().filter(|block| block.end.is_none()) {
+                    // The previous block wasn't finished properly
+                    block.end = Some(pos);
+                }
+                blocks.push(CommandBlock::new(pos));
+                output_time = None;
+            }
+
+            let block = match blocks.last_mut() {
+                Some(block) if block.end.is_none() => block,
+                _ => continue,
+            };
+
+            match mark.mark {
+                PromptStart => {},
+                CommandStart => block.command = Some(pos),
+                OutputStart => {
+                    block.output = Some(pos);
+                    output_time = Some(mark.time);
+                },
+                CommandFinished(exit_code) => {
+                    block.end = Some(pos);
+                    block.exit_code = exit_code;
+                    block.duration = output_time.map(|time| mark.time.duration_since(time));
+                },
+            }
+        }
+    }
+
+    blocks
+}
+
+/// Textual content of the output of a command block, lines are separated by `\n`
+pub fn command_output(screen: &Screen, block: &CommandBlock) -> String {
+    let start = match block.output {
+        Some(start) => start,
+        None => return String::new(),
+    };
+    let end = block.end.unwrap_or(BlockPos { line: screen.history_len(), x: 0 });
+
+    let mut lines = vec![];
+    for line in start.line ..= end.line.min(screen.history_len().saturating_sub(1)) {
+        let from = if line == start.line { start.x as usize } else { 0 };
+        let to = if line == end.line { end.x as usize } else { usize::MAX };
+        if line == end.line && to == 0 && line > start.line {
+            break;
+        }
+        lines.push(screen.history_text(line, from, to));
+    }
+
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    lines.join("\n")
+}
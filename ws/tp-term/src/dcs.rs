@@ -0,0 +1,138 @@
+/// DCS (Device Control String) handlers
+///
+/// The parser announces a DCS via `VTDispatch::dcs_hook()`, `TermState` then picks
+/// a `DcsHandler` based on the DCS's intermediate and final bytes. The handler receives
+/// the control string data and does its work once the string is terminated.
+
+use std::fmt;
+use std::sync::Arc;
+
+use ::vt::*;
+use ::TermState;
+use ::sixel::SixelDecoder;
+
+
+/// Maximum size of the data buffered by the simple handlers below
+const DATA_MAX: usize = 4096;
+
+pub trait DcsHandler: fmt::Debug {
+    /// Control string data
+    fn put(&mut self, byte: u8);
+    /// The control string was terminated, this is called only if it was terminated properly (by ST)
+    fn unhook(self: Box<Self>, term: &mut TermState);
+}
+
+/// Get a handler for a DCS, see `VTDispatch::dcs_hook()`
+pub fn handler(private: u8, interm: u8, params: &[i32], action: u8) -> Option<Box<dyn DcsHandler>> {
+    match (private, interm, action) {
+        (0, 0, b'q') => Some(Box::new(Sixel(SixelDecoder::new(params)))),
+        (0, b'$', b'q') => Some(Box::new(Decrqss::default())),
+        (0, b'+', b'q') => Some(Box::new(XtGetTcap::default())),
+        _ => None,
+    }
+}
+
+
+/// DECRQSS, Request Selection or Setting
+#[derive(Debug, Default)]
+struct Decrqss {
+    data: Vec<u8>,
+}
+
+impl DcsHandler for Decrqss {
+    fn put(&mut self, byte: u8) {
+        if self.data.len() < DATA_MAX {
+            self.data.push(byte);
+        }
+    }
+
+    fn unhook(self: Box<Self>, term: &mut TermState) {
+        let setting = match &self.data[..] {
+            b"m" => VTSetting::Sgr,
+            b"r" => VTSetting::ScrollRegion,
+            b" q" => VTSetting::CursorStyle,
+            _ => VTSetting::Invalid,
+        };
+
+        term.report_request(VTReport::Setting(setting));
+    }
+}
+
+
+/// XTGETTCAP, Request Termcap/Terminfo String.
+/// The data is a `;`-separated list of hex-encoded capability names.
+#[derive(Debug, Default)]
+struct XtGetTcap {
+    data: Vec<u8>,
+}
+
+impl DcsHandler for XtGetTcap {
+    fn put(&mut self, byte: u8) {
+        if self.data.len() < DATA_MAX {
+            self.data.push(byte);
+        }
+    }
+
+    fn unhook(self: Box<Self>, term: &mut TermState) {
+        for name in self.data.split(|b| *b == b';') {
+            // Names that fail to decode are reported as unknown
+            let name = hex_decode(name).unwrap_or_else(|_| name.to_vec());
+            term.report_request(VTReport::TermCap(String::from_utf8_lossy(&name).into_owned()));
+        }
+    }
+}
+
+
+/// Sixel graphics, the image is placed at the cursor
+#[derive(Debug)]
+struct Sixel(SixelDecoder);
+
+impl DcsHandler for Sixel {
+    fn put(&mut self, byte: u8) {
+        self.0.put(byte);
+    }
+
+    fn unhook(self: Box<Self>, term: &mut TermState) {
+        if let Some(image) = self.0.finish() {
+            term.screen_mut().put_image(Arc::new(image));
+        }
+    }
+}
+
+
+/// Terminfo capabilities reported by XTGETTCAP. Boolean capabilities have no value.
+/// String values are the actual control strings (ie. with escape characters decoded) in terminfo's parametrized format.
+const TERMCAPS: &[(&str, Option<&str>)] = &[
+    ("TN", Some("xterm-256color")),
+    ("name", Some("xterm-256color")),
+    ("Co", Some("256")),
+    ("colors", Some("256")),
+    ("RGB", Some("8")),
+    ("Tc", None),
+    ("setrgbf", Some("\x1b[38;2;%p1%d;%p2%d;%p3%dm")),
+    ("setrgbb", Some("\x1b[48;2;%p1%d;%p2%d;%p3%dm")),
+    ("Ss", Some("\x1b[%p1%d q")),
+    ("Se", Some("\x1b[2 q")),
+    ("kbs", Some("\x7f")),
+];
+
+/// Look up a terminfo capability. Returns `None` for unknown capabilities,
+/// `Some(None)` for boolean capabilities and `Some(Some(value))` for string and numeric ones.
+pub fn termcap(name: &str) -> Option<Option<&'static str>> {
+    TERMCAPS.iter().find(|cap| cap.0 == name).map(|cap| cap.1)
+}
+
+pub fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+pub fn hex_decode(data: &[u8]) -> Result<Vec<u8>, ()> {
+    if !data.len().is_multiple_of(2) {
+        return Err(());
+    }
+
+    let digit = |b: u8| (b as char).to_digit(16).map(|d| d as u8).ok_or(());
+    data.chunks(2)
+        .map(|pair| Ok(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
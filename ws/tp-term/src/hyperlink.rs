@@ -0,0 +1,75 @@
+/// Hyperlink support (OSC 8)
+///
+/// Hyperlink URIs are interned in a `Hyperlinks` table owned by a `Screen`,
+/// cells only carry a small `LinkId` handle as part of their `Style`.
+
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::rc::Rc;
+
+use ::vt::VTHyperlink;
+
+
+/// Maximum number of distinct hyperlinks a table holds, links beyond that are not recorded
+pub const HYPERLINKS_MAX: usize = 64 * 1024;
+
+/// A handle referencing a hyperlink in a `Hyperlinks` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LinkId(NonZeroU32);
+
+impl LinkId {
+    fn index(&self) -> usize { self.0.get() as usize - 1 }
+
+    pub(crate) fn from_u32(id: u32) -> Option<LinkId> { NonZeroU32::new(id).map(LinkId) }
+    pub(crate) fn as_u32(&self) -> u32 { self.0.get() }
+}
+
+/// Interned hyperlink table, each link is stored once and shared by the lookup index
+#[derive(Debug, Default)]
+pub struct Hyperlinks {
+    links: Vec<Rc<VTHyperlink>>,
+    index: HashMap<Rc<VTHyperlink>, LinkId>,
+}
+
+impl Hyperlinks {
+    pub fn new() -> Hyperlinks {
+        Hyperlinks::default()
+    }
+
+    /// Get a handle for `link`, adding it to the table if it's not there yet.
+    /// Returns `None` if the table is full.
+    pub fn intern(&mut self, link: VTHyperlink) -> Option<LinkId> {
+        if let Some(id) = self.index.get(&link) {
+            return Some(*id);
+        }
+        self.insert(Rc::new(link))
+    }
+
+    fn insert(&mut self, link: Rc<VTHyperlink>) -> Option<LinkId> {
+        if self.links.len() >= HYPERLINKS_MAX {
+            return None;
+        }
+
+        let id = LinkId::from_u32(self.links.len() as u32 + 1)?;
+        self.links.push(link.clone());
+        self.index.insert(link, id);
+        Some(id)
+    }
+
+    /// Intern the link `id` refers to into the table `to`, returning its handle there
+    pub(crate) fn copy_to(&self, id: LinkId, to: &mut Hyperlinks) -> Option<LinkId> {
+        let link = self.links.get(id.index())?;
+        match to.index.get(link) {
+            Some(id) => Some(*id),
+            None => to.insert(link.clone()),
+        }
+    }
+
+    /// Look up a hyperlink by its handle
+    pub fn get(&self, id: LinkId) -> Option<&VTHyperlink> {
+        self.links.get(id.index()).map(|link| &**link)
+    }
+
+    pub fn len(&self) -> usize { self.links.len() }
+    pub fn is_empty(&self) -> bool { self.links.is_empty() }
+}
@@ -0,0 +1,86 @@
+/// Inline images
+///
+/// Images are decoded into RGBA bitmaps and placed on the screen as slices, one per line
+/// the image covers (see `LineImage`). This way they scroll along with the text, and are dropped
+/// together with the lines they're placed on.
+
+use std::fmt;
+use std::sync::Arc;
+
+
+/// Maximum width and height of an image in pixels
+pub const IMAGE_SIZE_MAX: u32 = 4096;
+
+/// An RGBA bitmap
+#[derive(Clone, PartialEq, Eq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    /// Pixel data, row by row, 4 bytes per pixel
+    data: Vec<u8>,
+}
+
+impl Image {
+    /// Create an image from RGBA data, `data` is truncated or padded with transparent pixels as needed
+    pub fn new(width: u32, height: u32, mut data: Vec<u8>) -> Image {
+        data.resize((width * height * 4) as usize, 0);
+        Image { width, height, data }
+    }
+
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+
+    /// Pixel data, row by row, 4 bytes (R, G, B, A) per pixel
+    pub fn data(&self) -> &[u8] { &self.data }
+
+    /// Pixel at `x`, `y` as RGBA
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let i = ((y * self.width + x) * 4) as usize;
+        Some([self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]])
+    }
+}
+
+impl fmt::Debug for Image {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Image")
+           .field("width", &self.width)
+           .field("height", &self.height)
+           .field("data", &format!("[_; {}]", self.data.len()))
+           .finish()
+    }
+}
+
+/// An image placed on the screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImagePlacement {
+    pub image: Arc<Image>,
+    /// Part of the image to display: x, y, width, height in pixels
+    pub source: (u32, u32, u32, u32),
+    /// Displayed size in pixels, the source is scaled to this size
+    pub size: (u32, u32),
+    /// Offset from the top left corner of the first cell in pixels
+    pub offset: (u32, u32),
+    /// Drawing order of overlapping images, images with negative z-index are drawn below text
+    pub z: i32,
+    /// Image id and placement id for images placed with the kitty graphics protocol
+    pub id: Option<(u32, u32)>,
+}
+
+impl ImagePlacement {
+    /// Display the whole image at its natural size
+    pub fn new(image: Arc<Image>) -> ImagePlacement {
+        let (width, height) = (image.width(), image.height());
+        ImagePlacement {
+            image,
+            source: (0, 0, width, height),
+            size: (width, height),
+            offset: (0, 0),
+            z: 0,
+            id: None,
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use std::io::{self, Write};
 
 use ::vt::*;
-use ::screen::Screen;
+use ::screen::{Screen, Style};
+use ::base64;
+use ::dcs;
 
 
 /// Input Key
@@ -68,12 +70,107 @@ impl Modifier {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    /// No button, used for motion without any button pressed
+    None,
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    /// Back and forward buttons
+    Back,
+    Forward,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    Press,
+    Release,
+    Motion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub action: MouseAction,
+    /// The button pressed or released, for motion the button held, if any
+    pub button: MouseButton,
+    pub modifier: Modifier,
+    /// Cell position, column and row starting at `0`
+    pub cell: (u32, u32),
+    /// Position in pixels from the top left corner of the screen
+    pub pixel: (u32, u32),
+}
+
+impl MouseEvent {
+    fn is_wheel(&self) -> bool {
+        use self::MouseButton::*;
+        matches!(self.button, WheelUp | WheelDown | WheelLeft | WheelRight)
+    }
+
+    /// Whether the event is reported in the mouse tracking mode `mode`
+    fn is_reported(&self, mode: VTMode) -> bool {
+        match self.action {
+            MouseAction::Press => mode.intersects(VTMode::MOUSE_TRACKING),
+            // Wheel "buttons" are never released
+            MouseAction::Release => !self.is_wheel() && mode.intersects(VTMode::MOUSE_TRACKING - VTMode::MOUSE_X10),
+            MouseAction::Motion => mode.contains(VTMode::MOUSE_ANY)
+                || mode.contains(VTMode::MOUSE_BUTTON) && self.button != MouseButton::None,
+        }
+    }
+
+    /// Button code as used by the xterm mouse protocol, without the position
+    fn button_code(&self, mode: VTMode) -> u32 {
+        use self::MouseButton::*;
+
+        let release = self.action == MouseAction::Release && !mode.intersects(VTMode::MOUSE_SGR | VTMode::MOUSE_SGR_PIXELS);
+        let mut code = match self.button {
+            // Releases don't specify the button, except in SGR encodings
+            _ if release => 3,
+            None => 3,
+            Left => 0,
+            Middle => 1,
+            Right => 2,
+            WheelUp => 64,
+            WheelDown => 65,
+            WheelLeft => 66,
+            WheelRight => 67,
+            Back => 128,
+            Forward => 129,
+        };
+
+        if self.action == MouseAction::Motion {
+            code += 32;
+        }
+
+        // X10 mode doesn't report modifiers
+        if !mode.contains(VTMode::MOUSE_X10) {
+            if self.modifier.contains(Modifier::SHIFT) { code += 4; }
+            if self.modifier.contains(Modifier::ALT) { code += 8; }
+            if self.modifier.contains(Modifier::CONTROL) { code += 16; }
+        }
+
+        code
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InputData<'a> {
     Key(Key, Modifier),
     FKey(u8, Modifier),
     Char(char, Modifier),
     Str(&'a str),
+    /// Mouse event, only sent to the program if it enabled mouse tracking
+    Mouse(MouseEvent),
+    /// Pasted text, control characters are stripped and it is bracketed if the program asked for it
+    Paste(&'a str),
+    /// The terminal window gained focus, only reported if the program enabled focus events
+    FocusIn,
+    /// The terminal window lost focus
+    FocusOut,
     Empty,
 }
 
@@ -215,6 +312,81 @@ impl VTInput {
         Ok(size)
     }
 
+    /// Encodes a mouse event according to the tracking mode and encoding in `mode`.
+    /// Events not reported in the current mode, or which cannot be encoded, produce no output.
+    fn input_mouse(event: MouseEvent, mode: VTMode, mut buffer: &mut [u8]) -> Result<usize, ()> {
+        if !event.is_reported(mode) {
+            return Ok(0);
+        }
+
+        let code = event.button_code(mode);
+        let (x, y) = (event.cell.0 + 1, event.cell.1 + 1);
+        let mut data = vec![];
+
+        if mode.intersects(VTMode::MOUSE_SGR | VTMode::MOUSE_SGR_PIXELS) {
+            let (x, y) = if mode.contains(VTMode::MOUSE_SGR_PIXELS) {
+                (event.pixel.0 + 1, event.pixel.1 + 1)
+            } else {
+                (x, y)
+            };
+            let action = if event.action == MouseAction::Release { 'm' } else { 'M' };
+            data.extend(format!("\x1b[<{};{};{}{}", code, x, y, action).bytes());
+        } else if mode.contains(VTMode::MOUSE_URXVT) {
+            data.extend(format!("\x1b[{};{};{}M", code + 32, x, y).bytes());
+        } else if mode.contains(VTMode::MOUSE_UTF8) {
+            data.extend(b"\x1b[M");
+            for &value in [code, x, y].iter() {
+                // Values are offset by 32, 2047 is the largest value that fits in two UTF-8 bytes
+                match ::std::char::from_u32(value + 32) {
+                    Some(ch) if value + 32 <= 2047 => {
+                        let mut utf8 = [0; 4];
+                        data.extend(ch.encode_utf8(&mut utf8).bytes());
+                    },
+                    _ => return Ok(0),
+                }
+            }
+        } else {
+            // Each value is a single byte offset by 32, positions past 223 can't be encoded
+            data.extend(b"\x1b[M");
+            for &value in [code, x, y].iter() {
+                if value + 32 > 255 {
+                    return Ok(0);
+                }
+                data.push((value + 32) as u8);
+            }
+        }
+
+        if data.len() > buffer.len() {
+            return Err(());
+        }
+        buffer.write(&data).map_err(|_| ())
+    }
+
+    /// Removes control characters that could make pasted text do more than insert itself,
+    /// such as an embedded end of bracketed paste. Line breaks are sent as CR, like the Return key.
+    fn paste_sanitize(text: &str) -> String {
+        text.replace("\x1b[200~", "")
+            .replace("\x1b[201~", "")
+            .replace("\r\n", "\r")
+            .chars()
+            .filter_map(|ch| match ch {
+                '\n' | '\r' => Some('\r'),
+                '\t' => Some(ch),
+                '\x00' ... '\x1f' | '\x7f' ... '\u{9f}' => None,
+                _ => Some(ch),
+            })
+            .collect()
+    }
+
+    fn input_paste(text: &str, mode: VTMode, buffer: &mut [u8]) -> Result<usize, ()> {
+        let text = Self::paste_sanitize(text);
+        if mode.contains(VTMode::BRACKETED_PASTE) {
+            Self::write_answer(&format!("\x1b[200~{}\x1b[201~", text), buffer)
+        } else {
+            Self::write_answer(&text, buffer)
+        }
+    }
+
     pub fn input(&self, input: InputData, mode: VTMode, mut buffer: &mut [u8]) -> Result<usize, ()> {
         use InputData::*;
 
@@ -223,23 +395,157 @@ impl VTInput {
             FKey(num, modifier) => Self::input_fkey(num, modifier, buffer).map_err(|_| ()),
             Char(ch, modifier) => Self::input_char(ch, modifier, buffer),
             Str(s) => buffer.write(s.as_bytes()).map_err(|_| ()),
+            Mouse(event) => Self::input_mouse(event, mode, buffer),
+            Paste(text) => Self::input_paste(text, mode, buffer),
+            FocusIn | FocusOut if !mode.contains(VTMode::FOCUS_EVENTS) => Ok(0),
+            FocusIn => buffer.write(b"\x1b[I").map_err(|_| ()),
+            FocusOut => buffer.write(b"\x1b[O").map_err(|_| ()),
             Empty => Ok(0),
         }
     }
 
-    pub fn report_answer(&self, screen: &Screen, report: VTReport, mut buffer: &mut [u8]) -> Result<usize, ()> {
+    /// Writes a complete answer into `buffer`, fails if the buffer is not large enough
+    fn write_answer(answer: &str, mut buffer: &mut [u8]) -> Result<usize, ()> {
+        if answer.len() > buffer.len() {
+            return Err(());
+        }
+        buffer.write(answer.as_bytes()).map_err(|_| ())
+    }
+
+    pub fn clipboard_answer(&self, selection: VTSelection, data: &str, buffer: &mut [u8]) -> Result<usize, ()> {
+        let answer = format!("\x1b]52;{};{}\x1b\\", selection.encode() as char, base64::encode(data.as_bytes()));
+        Self::write_answer(&answer, buffer)
+    }
+
+    /// Answers a palette query in the xterm format, ie. `rgb:rrrr/gggg/bbbb`
+    pub fn color_answer(&self, slot: VTPaletteSlot, rgb: (u8, u8, u8), buffer: &mut [u8]) -> Result<usize, ()> {
+        let slot = match slot {
+            VTPaletteSlot::Indexed(index) => format!("4;{}", index),
+            VTPaletteSlot::Foreground => "10".to_string(),
+            VTPaletteSlot::Background => "11".to_string(),
+            VTPaletteSlot::Cursor => "12".to_string(),
+        };
+
+        // Components are scaled to 16 bits, ie. 0xab becomes 0xabab
+        let (r, g, b) = (rgb.0 as u16 * 0x101, rgb.1 as u16 * 0x101, rgb.2 as u16 * 0x101);
+        let answer = format!("\x1b]{};rgb:{:04x}/{:04x}/{:04x}\x1b\\", slot, r, g, b);
+        Self::write_answer(&answer, buffer)
+    }
+
+    /// SGR parameters that would set up `style`, starting with a reset
+    fn sgr_params(style: &Style) -> String {
+        let mut params = vec!["0".to_string()];
+
+        let renditions = [
+            (VTRendition::BOLD, "1"),
+            (VTRendition::DIM, "2"),
+            (VTRendition::ITALIC, "3"),
+            (VTRendition::BLINKING, "5"),
+            (VTRendition::RAPID_BLINK, "6"),
+            (VTRendition::INVERSE, "7"),
+            (VTRendition::INVISIBLE, "8"),
+            (VTRendition::STRIKE, "9"),
+            (VTRendition::OVERLINE, "53"),
+        ];
+        for &(rend, param) in renditions.iter() {
+            if style.rendition.contains(rend) {
+                params.push(param.to_string());
+            }
+        }
+
+        match style.underline {
+            VTUnderline::None => {},
+            VTUnderline::Single => params.push("4".to_string()),
+            underline => params.push(format!("4:{}", underline as u8)),
+        }
+
+        // `base` is 30 for foreground, 40 for background
+        let color = |color: VTColor, base: u8| match color {
+            VTColor::DefaultFg | VTColor::DefaultBg => None,
+            VTColor::Indexed(i @ 0 ... 7) => Some(format!("{}", base + i)),
+            VTColor::Indexed(i @ 8 ... 15) => Some(format!("{}", base + 60 + i - 8)),
+            VTColor::Indexed(i) => Some(format!("{};5;{}", base + 8, i)),
+            VTColor::Rgb(r, g, b) => Some(format!("{};2;{};{};{}", base + 8, r, g, b)),
+        };
+        params.extend(color(style.col_fg, 30));
+        params.extend(color(style.col_bg, 40));
+        params.extend(match style.col_ul {
+            Some(VTColor::Indexed(i)) => Some(format!("58;5;{}", i)),
+            Some(VTColor::Rgb(r, g, b)) => Some(format!("58;2;{};{};{}", r, g, b)),
+            _ => None,
+        });
+
+        params.join(";")
+    }
+
+    /// Answer to DECRQSS
+    fn setting_answer(screen: &Screen, setting: VTSetting) -> String {
+        let setting = match setting {
+            VTSetting::Sgr => format!("{}m", Self::sgr_params(&screen.current_style())),
+            VTSetting::ScrollRegion => {
+                let (top, bottom) = screen.scroll_region();
+                format!("{};{}r", top, bottom)
+            },
+            VTSetting::CursorStyle => {
+                let shape = match screen.cursor_shape() {
+                    VTCursorShape::Block => 1,
+                    VTCursorShape::Underline => 3,
+                    VTCursorShape::Bar => 5,
+                };
+                format!("{} q", if screen.cursor_blink() { shape } else { shape + 1 })
+            },
+            VTSetting::Invalid => return "\x1bP0$r\x1b\\".to_string(),
+        };
+
+        format!("\x1bP1$r{}\x1b\\", setting)
+    }
+
+    /// Answer to XTGETTCAP
+    fn termcap_answer(name: &str) -> String {
+        let name_hex = dcs::hex_encode(name.as_bytes());
+        match dcs::termcap(name) {
+            Some(Some(value)) => format!("\x1bP1+r{}={}\x1b\\", name_hex, dcs::hex_encode(value.as_bytes())),
+            Some(None) => format!("\x1bP1+r{}\x1b\\", name_hex),
+            None => format!("\x1bP0+r{}\x1b\\", name_hex),
+        }
+    }
+
+    pub fn report_answer(&self, screen: &Screen, report: VTReport, buffer: &mut [u8]) -> Result<usize, ()> {
         use VTReport::*;
 
         let cursor = screen.cursor();
-        let cursor = format!("\x1b[{};{}R", cursor.0, cursor.1);
-        buffer.write(match report {
-            AnswerBack => b"TeePee",
-            PrimaryAttrs => b"\x1b[?1;2c",
-            SecondaryAttrs => b"\x1b>0;0;0c",    // TODO: version number?
-            DeviceStatus => b"\x1b[0n",
-            CursorPos => cursor.as_bytes(),
-            TermParams0 => b"\x1b[2;1;1;120;120;1;0;x",     // Made-up numbers
-            TermParams1 => b"\x1b[3;1;1;120;120;1;0;x",     // Made-up numbers
-        }).map_err(|_| ())
+        let answer = match report {
+            AnswerBack => "TeePee".to_string(),
+            PrimaryAttrs => "\x1b[?62;4c".to_string(),     // VT220 with sixel graphics
+            SecondaryAttrs => "\x1b>0;0;0c".to_string(),    // TODO: version number?
+            DeviceStatus => "\x1b[0n".to_string(),
+            CursorPos => format!("\x1b[{};{}R", cursor.0, cursor.1),
+            TermParams0 => "\x1b[2;1;1;120;120;1;0;x".to_string(),     // Made-up numbers
+            TermParams1 => "\x1b[3;1;1;120;120;1;0;x".to_string(),     // Made-up numbers
+            // Needs the selection content, see `clipboard_answer()`
+            Clipboard(_) => return Err(()),
+            Setting(setting) => Self::setting_answer(screen, setting),
+            TermCap(name) => Self::termcap_answer(&name),
+            Graphics(response) => format!("\x1b_G{}\x1b\\", response),
+            Mode { private, number } => {
+                // 0: not recognized, 1: set, 2: reset, 3: permanently set, 4: permanently reset
+                let state = match VTMode::from_number(private, number) {
+                    Some(VTModeKind::Mode(mode)) if screen.mode().contains(mode) => 1,
+                    Some(VTModeKind::Mode(_)) => 2,
+                    Some(VTModeKind::PermanentlySet) => 3,
+                    Some(VTModeKind::PermanentlyReset) => 4,
+                    None => 0,
+                };
+                format!("\x1b[{}{};{}$y", if private { "?" } else { "" }, number, state)
+            },
+            TextAreaPixels => {
+                let (size, cell) = (screen.size(), screen.cell_size());
+                format!("\x1b[4;{};{}t", size.1 * cell.1, size.0 * cell.0)
+            },
+            CellPixels => format!("\x1b[6;{};{}t", screen.cell_size().1, screen.cell_size().0),
+            TextAreaCells => format!("\x1b[8;{};{}t", screen.size().1, screen.size().0),
+        };
+
+        Self::write_answer(&answer, buffer)
     }
 }
@@ -0,0 +1,677 @@
+/// Kitty graphics protocol
+///
+/// Commands are sent as APCs: `ESC _ G <control data> ; <payload> ESC \`, where the control data
+/// is a `,`-separated list of `key=value` pairs and the payload is base64-encoded.
+/// Transmitted images are kept in an `ImageStore` owned by `TermState`, placements go to the image layer
+/// of the current screen (see `Screen::put_placement()`).
+///
+/// Supported: direct (optionally chunked), file, temporary file and shared memory transmission;
+/// PNG, RGB and RGBA formats with optional zlib compression; placements and delete commands.
+/// Not supported: animation, relative placements and unicode placeholders.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CString, OsStr};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::sync::Arc;
+
+use flate2::read::ZlibDecoder;
+use libc;
+use png;
+
+use ::vt::*;
+use ::base64;
+use ::image::{Image, ImagePlacement, IMAGE_SIZE_MAX};
+use ::TermState;
+
+
+/// Per-image quota: maximum size of the data of a single image in bytes, both as transmitted and decoded
+pub const IMAGE_DATA_MAX: usize = (IMAGE_SIZE_MAX * IMAGE_SIZE_MAX * 4) as usize;
+/// Default memory quota of the whole image store in bytes
+pub const STORE_QUOTA_DEFAULT: usize = 256 * 1024 * 1024;
+/// Maximum number of columns and rows a placement can span
+const PLACEMENT_CELLS_MAX: u32 = 1000;
+
+/// Ids assigned by the terminal (for images transmitted without an id) start here
+const AUTO_ID_START: u32 = 1 << 31;
+
+type Error = String;
+
+fn error<T>(code: &str, msg: &str) -> Result<T, Error> {
+    Err(format!("{}:{}", code, msg))
+}
+
+fn io_error(err: io::Error) -> Error {
+    let code = match err.kind() {
+        io::ErrorKind::NotFound => "ENOENT",
+        io::ErrorKind::PermissionDenied => "EPERM",
+        _ => "EIO",
+    };
+    format!("{}:{}", code, err)
+}
+
+
+/// A parsed command, fields are named after the protocol's keys
+#[derive(Debug, Clone)]
+struct Command {
+    /// `a`: `t` transmit, `T` transmit & put, `q` query, `p` put, `d` delete
+    action: u8,
+    /// `q`: 1 suppresses OK responses, 2 suppresses errors too
+    quiet: u32,
+    /// `f`: 24 RGB, 32 RGBA, 100 PNG
+    format: u32,
+    /// `t`: `d` direct, `f` file, `t` temporary file, `s` shared memory
+    medium: u8,
+    /// `o`: `z` zlib
+    compression: u8,
+    /// `m`: more chunks follow
+    more: bool,
+    /// `s`, `v`: image size in pixels for raw formats
+    width: u32,
+    height: u32,
+    /// `S`, `O`: size and offset of the data to read from a file or shared memory
+    size: u32,
+    offset: u32,
+    /// `i`, `I`, `p`: image id, image number, placement id
+    id: u32,
+    number: u32,
+    placement: u32,
+    /// `x`, `y`, `w`, `h`: source rectangle, also used by some delete commands
+    source: (u32, u32, u32, u32),
+    /// `c`, `r`: number of columns and rows to display the image in
+    cols: u32,
+    rows: u32,
+    /// `X`, `Y`: offset within the first cell in pixels
+    cell_offset: (u32, u32),
+    /// `z`: z-index
+    z: i32,
+    /// `C`: don't move the cursor
+    no_move: bool,
+    /// `d`: what to delete
+    delete: u8,
+}
+
+impl Default for Command {
+    fn default() -> Command {
+        Command {
+            action: b't',
+            quiet: 0,
+            format: 32,
+            medium: b'd',
+            compression: 0,
+            more: false,
+            width: 0,
+            height: 0,
+            size: 0,
+            offset: 0,
+            id: 0,
+            number: 0,
+            placement: 0,
+            source: (0, 0, 0, 0),
+            cols: 0,
+            rows: 0,
+            cell_offset: (0, 0),
+            z: 0,
+            no_move: false,
+            delete: b'a',
+        }
+    }
+}
+
+impl Command {
+    fn parse(control: &[u8]) -> Result<Command, Error> {
+        let mut cmd = Command::default();
+
+        for pair in control.split(|b| *b == b',').filter(|pair| !pair.is_empty()) {
+            if pair.len() < 3 || pair[1] != b'=' {
+                return error("EINVAL", "invalid control data");
+            }
+
+            let (key, value) = (pair[0], &pair[2..]);
+            let int = || -> Result<i64, Error> {
+                match ::std::str::from_utf8(value).ok().and_then(|v| v.parse().ok()) {
+                    Some(v) => Ok(v),
+                    None => error("EINVAL", "invalid control data"),
+                }
+            };
+            let uint = || int().map(|v| v.max(0).min(u32::MAX as i64) as u32);
+            let chr = || if value.len() == 1 { Ok(value[0]) } else { error("EINVAL", "invalid control data") };
+
+            match key {
+                b'a' => cmd.action = chr()?,
+                b'q' => cmd.quiet = uint()?,
+                b'f' => cmd.format = uint()?,
+                b't' => cmd.medium = chr()?,
+                b'o' => cmd.compression = chr()?,
+                b'm' => cmd.more = uint()? == 1,
+                b's' => cmd.width = uint()?,
+                b'v' => cmd.height = uint()?,
+                b'S' => cmd.size = uint()?,
+                b'O' => cmd.offset = uint()?,
+                b'i' => cmd.id = uint()?,
+                b'I' => cmd.number = uint()?,
+                b'p' => cmd.placement = uint()?,
+                b'x' => cmd.source.0 = uint()?,
+                b'y' => cmd.source.1 = uint()?,
+                b'w' => cmd.source.2 = uint()?,
+                b'h' => cmd.source.3 = uint()?,
+                b'c' => cmd.cols = uint()?,
+                b'r' => cmd.rows = uint()?,
+                b'X' => cmd.cell_offset.0 = uint()?,
+                b'Y' => cmd.cell_offset.1 = uint()?,
+                b'z' => cmd.z = int()?.max(i32::MIN as i64).min(i32::MAX as i64) as i32,
+                b'C' => cmd.no_move = uint()? == 1,
+                b'd' => cmd.delete = chr()?,
+                _ => {},   // Unknown keys are ignored
+            }
+        }
+
+        Ok(cmd)
+    }
+}
+
+
+#[derive(Debug)]
+struct StoredImage {
+    image: Arc<Image>,
+    number: u32,
+    /// Insertion order, used for eviction and image number lookup
+    serial: u64,
+}
+
+/// A chunked transmission in progress
+#[derive(Debug)]
+struct Transfer {
+    cmd: Command,
+    /// base64 payload received so far
+    data: Vec<u8>,
+}
+
+/// Images transmitted via the kitty graphics protocol, indexed by image id.
+/// When the memory quota is exceeded, the oldest images are evicted.
+/// Evicted images stay on the screen, but can no longer be placed.
+#[derive(Debug)]
+pub struct ImageStore {
+    images: HashMap<u32, StoredImage>,
+    transfer: Option<Transfer>,
+    /// Memory taken up by image data in bytes
+    size: usize,
+    quota: usize,
+    serial: u64,
+    next_id: u32,
+}
+
+impl ImageStore {
+    pub fn new() -> ImageStore {
+        ImageStore {
+            images: HashMap::new(),
+            transfer: None,
+            size: 0,
+            quota: STORE_QUOTA_DEFAULT,
+            serial: 0,
+            next_id: AUTO_ID_START,
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Arc<Image>> {
+        self.images.get(&id).map(|stored| &stored.image)
+    }
+
+    pub fn len(&self) -> usize { self.images.len() }
+    pub fn is_empty(&self) -> bool { self.images.is_empty() }
+
+    /// Memory taken up by image data in bytes
+    pub fn size(&self) -> usize { self.size }
+
+    pub fn quota(&self) -> usize { self.quota }
+
+    /// Set the memory quota in bytes, images are evicted as needed
+    pub fn set_quota(&mut self, quota: usize) {
+        self.quota = quota;
+        self.evict(0);
+    }
+
+    /// Evict the oldest images until `size` more bytes fit in the quota
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.quota {
+            let oldest = self.images.iter().min_by_key(|&(_, stored)| stored.serial).map(|(id, _)| *id);
+            match oldest {
+                Some(id) => { self.remove(id); },
+                None => break,
+            }
+        }
+    }
+
+    fn insert(&mut self, id: u32, number: u32, image: Image) -> Result<Arc<Image>, Error> {
+        self.remove(id);
+
+        let size = image.data().len();
+        if size > self.quota {
+            return error("ENOSPC", "image over quota");
+        }
+        self.evict(size);
+
+        let image = Arc::new(image);
+        self.serial += 1;
+        self.size += size;
+        self.images.insert(id, StoredImage { image: image.clone(), number, serial: self.serial });
+        Ok(image)
+    }
+
+    fn remove(&mut self, id: u32) -> bool {
+        match self.images.remove(&id) {
+            Some(stored) => {
+                self.size -= stored.image.data().len();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Id of the newest image with the `number`
+    fn find_number(&self, number: u32) -> Option<u32> {
+        self.images.iter()
+            .filter(|&(_, stored)| number != 0 && stored.number == number)
+            .max_by_key(|&(_, stored)| stored.serial)
+            .map(|(id, _)| *id)
+    }
+
+    fn free_id(&mut self) -> u32 {
+        while self.images.contains_key(&self.next_id) {
+            self.next_id = self.next_id.checked_add(1).unwrap_or(AUTO_ID_START);
+        }
+        self.next_id
+    }
+}
+
+impl Default for ImageStore {
+    fn default() -> ImageStore {
+        ImageStore::new()
+    }
+}
+
+
+/// Handle a graphics command, see `VTDispatch::kitty_graphics()`
+pub fn command(term: &mut TermState, data: &[u8]) {
+    let (control, payload) = match data.iter().position(|b| *b == b';') {
+        Some(i) => (&data[..i], &data[i + 1 ..]),
+        None => (data, &[][..]),
+    };
+
+    // Without the control data we don't even know whom to respond to
+    let cmd = match Command::parse(control) {
+        Ok(cmd) => cmd,
+        Err(_) => return,
+    };
+
+    let (mut cmd, payload) = match term.images.transfer.take() {
+        Some(mut transfer) => {
+            // Continuation of a chunked transmission, only the `m` key is relevant
+            if transfer.data.len() + payload.len() > IMAGE_DATA_MAX / 3 * 4 + 4 {
+                respond(term, &transfer.cmd, error("EFBIG", "image data too large"));
+                return;
+            }
+            transfer.data.extend_from_slice(payload);
+            if cmd.more {
+                term.images.transfer = Some(transfer);
+                return;
+            }
+            (transfer.cmd, transfer.data)
+        },
+        None if cmd.more && [b't', b'T', b'q'].contains(&cmd.action) => {
+            term.images.transfer = Some(Transfer { cmd, data: payload.to_vec() });
+            return;
+        },
+        None => (cmd, payload.to_vec()),
+    };
+
+    // Responses are only sent to commands that identify the image
+    let identified = cmd.id != 0 || cmd.number != 0;
+    let result = execute(term, &mut cmd, &payload);
+    if identified {
+        respond(term, &cmd, result);
+    }
+}
+
+fn respond(term: &mut TermState, cmd: &Command, result: Result<(), Error>) {
+    let quiet = match result {
+        Ok(_) => cmd.quiet >= 1,
+        Err(_) => cmd.quiet >= 2,
+    };
+    if quiet {
+        return;
+    }
+
+    let mut keys = vec![];
+    if cmd.id != 0 { keys.push(format!("i={}", cmd.id)); }
+    if cmd.number != 0 { keys.push(format!("I={}", cmd.number)); }
+    if cmd.placement != 0 { keys.push(format!("p={}", cmd.placement)); }
+
+    let message = result.err().unwrap_or_else(|| "OK".to_string());
+    term.report_request(VTReport::Graphics(format!("{};{}", keys.join(","), message)));
+}
+
+fn execute(term: &mut TermState, cmd: &mut Command, payload: &[u8]) -> Result<(), Error> {
+    match cmd.action {
+        b't' | b'T' | b'q' => {
+            let image = load(cmd, payload)?;
+            if cmd.action == b'q' {
+                return Ok(());
+            }
+
+            if cmd.id == 0 {
+                cmd.id = term.images.free_id();
+            }
+            let image = term.images.insert(cmd.id, cmd.number, image)?;
+            if cmd.action == b'T' {
+                put(term, cmd, image)?;
+            }
+            Ok(())
+        },
+        b'p' => {
+            if cmd.id == 0 {
+                cmd.id = term.images.find_number(cmd.number).unwrap_or(0);
+            }
+            match term.images.get(cmd.id).cloned() {
+                Some(image) => put(term, cmd, image),
+                None => error("ENOENT", "image not found"),
+            }
+        },
+        b'd' => {
+            delete(term, cmd);
+            Ok(())
+        },
+        _ => error("EINVAL", "unsupported action"),
+    }
+}
+
+
+/// Get the image data from the transmission medium and decode it
+fn load(cmd: &Command, payload: &[u8]) -> Result<Image, Error> {
+    let payload = match base64::decode(payload) {
+        Ok(payload) => payload,
+        Err(_) => return error("EINVAL", "invalid base64 data"),
+    };
+
+    let data = match cmd.medium {
+        b'd' => payload,
+        b'f' => read_file(Path::new(OsStr::from_bytes(&payload)), cmd, false)?,
+        b't' => read_file(Path::new(OsStr::from_bytes(&payload)), cmd, true)?,
+        b's' => read_shm(&payload, cmd)?,
+        _ => return error("EINVAL", "unsupported transmission medium"),
+    };
+
+    let data = match cmd.compression {
+        0 => data,
+        b'z' => inflate(&data)?,
+        _ => return error("EINVAL", "unsupported compression"),
+    };
+
+    decode(cmd, data)
+}
+
+/// Read `S` bytes (or everything up to the quota) at offset `O`
+fn read_data<R: Read + Seek>(reader: &mut R, cmd: &Command) -> Result<Vec<u8>, Error> {
+    reader.seek(SeekFrom::Start(cmd.offset as u64)).map_err(io_error)?;
+
+    let limit = if cmd.size > 0 { (cmd.size as usize).min(IMAGE_DATA_MAX) } else { IMAGE_DATA_MAX };
+    let mut data = vec![];
+    reader.take(limit as u64 + 1).read_to_end(&mut data).map_err(io_error)?;
+
+    match data.len() > limit {
+        true if cmd.size > 0 => { data.truncate(limit); Ok(data) },
+        true => error("EFBIG", "image data too large"),
+        false => Ok(data),
+    }
+}
+
+fn read_file(path: &Path, cmd: &Command, temporary: bool) -> Result<Vec<u8>, Error> {
+    // Resolve `..` and symlinks first so that the checks below apply to the file actually opened
+    let path = fs::canonicalize(path).map_err(io_error)?;
+
+    // Refuse to read from special filesystems
+    if ["/proc", "/sys", "/dev"].iter().any(|dir| path.starts_with(dir)) && !path.starts_with("/dev/shm") {
+        return error("EPERM", "special files are not allowed");
+    }
+
+    let mut file = File::open(&path).map_err(io_error)?;
+    if !file.metadata().map_err(io_error)?.is_file() {
+        return error("EINVAL", "not a regular file");
+    }
+    let data = read_data(&mut file, cmd);
+
+    // Only delete files that are evidently meant for us, directly in a temp directory
+    let temp_dirs = [env::temp_dir(), "/tmp".into(), "/dev/shm".into()];
+    let in_temp_dir = path.parent().is_some_and(|parent| {
+        temp_dirs.iter().filter_map(|dir| fs::canonicalize(dir).ok()).any(|dir| dir == parent)
+    });
+    let is_temp = in_temp_dir && path.file_name().is_some_and(|name| name.to_string_lossy().contains("tty-graphics-protocol"));
+    if temporary && is_temp {
+        let _ = fs::remove_file(&path);
+    }
+
+    data
+}
+
+fn read_shm(name: &[u8], cmd: &Command) -> Result<Vec<u8>, Error> {
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return error("EINVAL", "invalid shared memory name"),
+    };
+
+    let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDONLY, 0) };
+    if fd < 0 {
+        return Err(io_error(io::Error::last_os_error()));
+    }
+
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    let data = read_data(&mut file, cmd);
+    unsafe { libc::shm_unlink(name.as_ptr()); }
+    data
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut res = vec![];
+    if ZlibDecoder::new(data).take(IMAGE_DATA_MAX as u64 + 1).read_to_end(&mut res).is_err() {
+        return error("EINVAL", "invalid compressed data");
+    }
+
+    if res.len() > IMAGE_DATA_MAX {
+        return error("EFBIG", "image data too large");
+    }
+    Ok(res)
+}
+
+fn decode(cmd: &Command, mut data: Vec<u8>) -> Result<Image, Error> {
+    match cmd.format {
+        24 | 32 => {
+            let (width, height) = (cmd.width, cmd.height);
+            if width == 0 || height == 0 || width > IMAGE_SIZE_MAX || height > IMAGE_SIZE_MAX {
+                return error("EINVAL", "invalid image size");
+            }
+
+            let bpp = cmd.format as usize / 8;
+            let len = width as usize * height as usize * bpp;
+            if data.len() < len {
+                return error("ENODATA", "insufficient image data");
+            }
+
+            data.truncate(len);
+            let data = if bpp == 4 { data } else { rgba(&data, |px| [px[0], px[1], px[2], 0xff], 3) };
+            Ok(Image::new(width, height, data))
+        },
+        100 => decode_png(&data),
+        _ => error("EINVAL", "unsupported format"),
+    }
+}
+
+/// Convert pixels of `bpp` bytes each to RGBA
+fn rgba<F>(data: &[u8], convert: F, bpp: usize) -> Vec<u8> where F: Fn(&[u8]) -> [u8; 4] {
+    let mut res = Vec::with_capacity(data.len() / bpp * 4);
+    for px in data.chunks(bpp) {
+        res.extend_from_slice(&convert(px));
+    }
+    res
+}
+
+fn decode_png(data: &[u8]) -> Result<Image, Error> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = match decoder.read_info() {
+        Ok(reader) => reader,
+        Err(_) => return error("EBADPNG", "invalid PNG data"),
+    };
+
+    let (width, height) = (reader.info().width, reader.info().height);
+    if width == 0 || height == 0 || width > IMAGE_SIZE_MAX || height > IMAGE_SIZE_MAX {
+        return error("EINVAL", "invalid image size");
+    }
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = match reader.next_frame(&mut buffer) {
+        Ok(frame) => frame,
+        Err(_) => return error("EBADPNG", "invalid PNG data"),
+    };
+
+    let pixels = &buffer[.. frame.buffer_size()];
+    let data = match frame.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => rgba(pixels, |px| [px[0], px[1], px[2], 0xff], 3),
+        png::ColorType::GrayscaleAlpha => rgba(pixels, |px| [px[0], px[0], px[0], px[1]], 2),
+        png::ColorType::Grayscale => rgba(pixels, |px| [px[0], px[0], px[0], 0xff], 1),
+        png::ColorType::Indexed => return error("EBADPNG", "unsupported PNG color type"),
+    };
+
+    Ok(Image::new(width, height, data))
+}
+
+
+fn put(term: &mut TermState, cmd: &Command, image: Arc<Image>) -> Result<(), Error> {
+    // Clip the source rectangle
+    let (width, height) = (image.width(), image.height());
+    let (x, y) = (cmd.source.0.min(width), cmd.source.1.min(height));
+    let w = if cmd.source.2 == 0 { width - x } else { cmd.source.2.min(width - x) };
+    let h = if cmd.source.3 == 0 { height - y } else { cmd.source.3.min(height - y) };
+    if w == 0 || h == 0 {
+        return error("EINVAL", "empty source rectangle");
+    }
+
+    // Scale to the requested number of cells, keep aspect ratio if only one dimension is given
+    let (cell_w, cell_h) = term.screen().cell_size();
+    let (cols, rows) = (cmd.cols.min(PLACEMENT_CELLS_MAX), cmd.rows.min(PLACEMENT_CELLS_MAX));
+    let scale = |a: u32, b: u32, c: u32| (a as u64 * b as u64 / c as u64) as u32;
+    let size = match (cols, rows) {
+        (0, 0) => (w, h),
+        (cols, 0) => (cols * cell_w, scale(h, cols * cell_w, w)),
+        (0, rows) => (scale(w, rows * cell_h, h), rows * cell_h),
+        (cols, rows) => (cols * cell_w, rows * cell_h),
+    };
+    let size = (size.0.min(PLACEMENT_CELLS_MAX * cell_w), size.1.min(PLACEMENT_CELLS_MAX * cell_h));
+
+    let placement = ImagePlacement {
+        image,
+        source: (x, y, w, h),
+        size,
+        offset: (cmd.cell_offset.0.min(cell_w - 1), cmd.cell_offset.1.min(cell_h - 1)),
+        z: cmd.z,
+        id: Some((cmd.id, cmd.placement)),
+    };
+
+    // Putting an existing placement again moves it
+    if cmd.placement != 0 {
+        let id = placement.id;
+        term.screen_mut().remove_images(|img, _| img.placement.id == id);
+    }
+
+    term.screen_mut().put_placement(placement, !cmd.no_move);
+    Ok(())
+}
+
+fn delete(term: &mut TermState, cmd: &Command) {
+    let what = cmd.delete.to_ascii_lowercase();
+    let (id, placement) = match what {
+        b'n' => (term.images.find_number(cmd.number).unwrap_or(0), cmd.placement),
+        _ => (cmd.id, cmd.placement),
+    };
+
+    // Cell coordinates are 1-indexed
+    let cursor = term.screen().cursor();
+    let cursor = (cursor.0 - 1, cursor.1 as usize - 1);
+    let cell = (cmd.source.0.saturating_sub(1), cmd.source.1.saturating_sub(1) as usize);
+    let range = (cmd.source.0, cmd.source.1);
+
+    let removed = term.screen_mut().remove_images(|img, line| {
+        let covers = |x: u32| img.x <= x && x < img.x + img.cols;
+        let (img_id, img_placement) = match img.placement.id {
+            Some(id) => id,
+            None => return false,   // Not placed with this protocol
+        };
+
+        match what {
+            b'a' => true,
+            b'i' | b'n' => img_id == id && (placement == 0 || img_placement == placement),
+            b'c' => line == cursor.1 && covers(cursor.0),
+            b'p' => line == cell.1 && covers(cell.0),
+            b'q' => line == cell.1 && covers(cell.0) && img.placement.z == cmd.z,
+            b'x' => covers(cell.0),
+            b'y' => line == cell.1,
+            b'z' => img.placement.z == cmd.z,
+            b'r' => img_id >= range.0 && img_id <= range.1,
+            _ => false,
+        }
+    });
+
+    // Uppercase variants free the image data as well, unless still placed
+    if cmd.delete.is_ascii_uppercase() {
+        let mut ids: Vec<u32> = removed.iter().filter_map(|p| p.id).map(|id| id.0).collect();
+        if what == b'i' || what == b'n' {
+            ids.push(id);
+        }
+
+        for id in ids {
+            let placed = term.screen().images().any(|(_, img)| img.placement.id.map(|id| id.0) == Some(id));
+            if !placed {
+                term.images.remove(id);
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_parse() {
+        let cmd = Command::parse(b"a=T,f=24,s=10,v=20,i=5,z=-3,C=1,X=2,q=2").unwrap();
+        assert_eq!((cmd.action, cmd.format, cmd.width, cmd.height, cmd.id), (b'T', 24, 10, 20, 5));
+        assert_eq!((cmd.z, cmd.no_move, cmd.cell_offset, cmd.quiet), (-3, true, (2, 0), 2));
+        assert_eq!((cmd.medium, cmd.delete), (b'd', b'a'));
+
+        assert!(Command::parse(b"a=T,f=x").is_err());
+        assert!(Command::parse(b"a=").is_err());
+        assert!(Command::parse(b"a=T,,K=1").is_ok());
+    }
+
+    #[test]
+    fn store_quota() {
+        let mut store = ImageStore::new();
+        store.set_quota(100);
+
+        store.insert(1, 0, Image::new(4, 4, vec![])).unwrap();
+        store.insert(2, 7, Image::new(4, 4, vec![])).unwrap();
+        assert_eq!((store.len(), store.size()), (1, 64));
+        assert!(store.get(1).is_none());
+        assert_eq!(store.find_number(7), Some(2));
+
+        assert!(store.insert(3, 0, Image::new(8, 8, vec![])).is_err());
+        store.remove(2);
+        assert_eq!((store.len(), store.size()), (0, 0));
+    }
+}
@@ -1,35 +1,159 @@
 #[macro_use] extern crate bitflags;
 extern crate smallvec;
 extern crate unicode_width;
+extern crate libc;
+extern crate png;
+extern crate flate2;
 
 use std::mem;
 use std::ops;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use smallvec::{SmallVec, Drain};
 
 pub mod utf8;
+mod base64;
 mod smallstring;
 mod vt;
 pub mod scrollback;
 mod screen;
 mod input;
+mod hyperlink;
+mod blocks;
+mod dcs;
+mod image;
+mod sixel;
+mod kitty;
 pub use smallstring::*;
 pub use vt::*;
 pub use scrollback::MemScrollback;
 pub use screen::*;
 pub use input::*;
+pub use hyperlink::*;
+pub use blocks::*;
+pub use image::*;
+pub use kitty::{ImageStore, IMAGE_DATA_MAX, STORE_QUOTA_DEFAULT};
 
 
 pub type ReportRequests = SmallVec<[VTReport; 4]>;
+pub type ClipboardRequests = SmallVec<[(VTSelection, String); 1]>;
+pub type PaletteRequests = SmallVec<[PaletteRequest; 4]>;
+pub type AppRequests = SmallVec<[AppRequest; 1]>;
+
+/// Maximum number of user variables per session
+pub const USER_VARS_MAX: usize = 64;
+/// Maximum length of a user variable name and value, in bytes
+pub const USER_VAR_NAME_MAX: usize = 128;
+pub const USER_VAR_VALUE_MAX: usize = 4096;
+
+/// Maximum number of clipboard requests awaiting confirmation, further requests are dropped
+pub const CLIPBOARD_PENDING_MAX: usize = 8;
+
+/// Maximum number of saved states per mode, the oldest states are dropped
+pub const MODE_SAVE_MAX: usize = 16;
+
+/// Maximum number of entries on the title stack, the oldest entries are dropped
+pub const TITLE_STACK_MAX: usize = 10;
+
+/// Synchronized updates not finished within this time are displayed anyway,
+/// so that a program that crashes mid-frame can't freeze the display
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Palette manipulation requested by the program running in the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteRequest {
+    Set(VTPaletteSlot, (u8, u8, u8)),
+    /// Reset to default, `None` means all indexed colors
+    Reset(Option<VTPaletteSlot>),
+    /// Should be answered using `Term::color_answer()`
+    Query(VTPaletteSlot),
+}
+
+/// Requests to the application made by the program running in the terminal (via `ESC _ T P`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppRequest {
+    /// Open a new tab, in the given directory if any.
+    /// Running a command in the new tab is deliberately not supported, as that would let anyone
+    /// able to write to the terminal (eg. a remote host or a `cat`-ed file) execute programs locally.
+    NewTab(Option<PathBuf>),
+    /// Window manipulation requested with XTWINOPS, see `TermState::window_policy()`
+    Window(VTWindowRequest),
+}
+
+/// Whether a terminal-initiated operation should be carried out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Allow,
+    /// The operation is passed on to the application, which should ask the user for confirmation
+    Ask,
+    /// The operation is dropped
+    Deny,
+}
+
+/// Clipboard access held back until the user confirms it, see `TermState::clipboard_confirm()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardRequest {
+    Store(VTSelection, String),
+    Load(VTSelection),
+}
+
+/// Policy for clipboard access by programs running in the terminal (OSC 52)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClipboardPolicy {
+    pub read: Permission,
+    pub write: Permission,
+}
+
+impl Default for ClipboardPolicy {
+    fn default() -> ClipboardPolicy {
+        ClipboardPolicy {
+            read: Permission::Ask,
+            write: Permission::Allow,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct TermState {
     mode: VTMode,
+    /// States of DEC private modes saved with XTSAVE, by mode number
+    modes_saved: HashMap<u32, Vec<bool>>,
     screen_current: VTScreenChoice,
     screen_primary: Screen,
     screen_alternate: Screen,
     bell: bool,
     report_requests: ReportRequests,
+    /// Window title as set by OSC 0 or OSC 2
+    title: String,
+    /// Icon name (ie. the short title) as set by OSC 0 or OSC 1
+    icon_name: String,
+    title_changed: bool,
+    /// Icon names and titles saved with XTWINOPS 22
+    title_stack: Vec<(Option<String>, Option<String>)>,
+    /// Working directory as reported by the shell with OSC 7
+    working_dir: Option<PathBuf>,
+    working_dir_host: String,
+    clipboard_policy: ClipboardPolicy,
+    window_policy: Permission,
+    clipboard_requests: ClipboardRequests,
+    /// Clipboard requests awaiting confirmation, oldest first
+    clipboard_pending: Vec<ClipboardRequest>,
+    palette_requests: PaletteRequests,
+    /// Per-session variables set by programs via `ESC _ T P`
+    user_vars: HashMap<String, String>,
+    app_requests: AppRequests,
+    /// Handler of the DCS in progress, if any
+    dcs: Option<Box<dyn dcs::DcsHandler>>,
+    /// Images transmitted with the kitty graphics protocol
+    images: ImageStore,
+    /// Start of the synchronized update in progress, if any
+    sync_start: Option<Instant>,
+    /// Whether a synchronized update has ended since the last `reset_sync_ended()`
+    sync_ended: bool,
+    /// Whether a full reset (RIS) also clears the scrollback
+    reset_scrollback: bool,
 }
 
 impl TermState {
@@ -38,11 +162,30 @@ impl TermState {
 
         TermState {
             mode: VTMode::default(),
+            modes_saved: HashMap::new(),
             screen_current: VTScreenChoice::default(),
             screen_primary: Screen::default().with_scrollback(scrollback),
             screen_alternate: Screen::default(),
             bell: false,
             report_requests: ReportRequests::new(),
+            title: String::new(),
+            icon_name: String::new(),
+            title_changed: false,
+            title_stack: vec![],
+            working_dir: None,
+            working_dir_host: String::new(),
+            clipboard_policy: ClipboardPolicy::default(),
+            window_policy: Permission::Ask,
+            clipboard_requests: ClipboardRequests::new(),
+            clipboard_pending: vec![],
+            palette_requests: PaletteRequests::new(),
+            user_vars: HashMap::new(),
+            app_requests: AppRequests::new(),
+            dcs: None,
+            images: ImageStore::new(),
+            sync_start: None,
+            sync_ended: false,
+            reset_scrollback: false,
         }
     }
 
@@ -51,6 +194,37 @@ impl TermState {
         self.screen_alternate.resize(cols, rows);
     }
 
+    /// Set the size of a cell in pixels, this determines how many cells images take up
+    pub fn set_cell_size(&mut self, width: u32, height: u32) {
+        self.screen_primary.set_cell_size(width, height);
+        self.screen_alternate.set_cell_size(width, height);
+    }
+
+    /// Set whether a full reset (RIS, eg. by the `reset` command) also clears the scrollback, it doesn't by default
+    pub fn set_reset_scrollback(&mut self, clear: bool) {
+        self.reset_scrollback = clear;
+    }
+
+    /// Current terminal modes, eg. whether the program requested mouse tracking
+    pub fn mode(&self) -> VTMode { self.mode }
+
+    /// Whether a synchronized update (DECSET 2026) is in progress, ie. the screen holds an incomplete frame
+    /// and shouldn't be painted. Updates that exceed `SYNC_TIMEOUT` are considered finished.
+    pub fn sync_update(&self) -> bool {
+        self.sync_deadline().is_some_and(|deadline| Instant::now() < deadline)
+    }
+
+    /// The time at which the synchronized update in progress, if any, times out
+    pub fn sync_deadline(&self) -> Option<Instant> {
+        self.sync_start.map(|start| start + SYNC_TIMEOUT)
+    }
+
+    /// Returns whether a synchronized update was finished by the program since the last call,
+    /// ie. a complete frame is ready to be painted
+    pub fn reset_sync_ended(&mut self) -> bool {
+        mem::replace(&mut self.sync_ended, false)
+    }
+
     pub fn reset_bell(&mut self) -> bool {
         mem::replace(&mut self.bell, false)
     }
@@ -58,6 +232,92 @@ impl TermState {
     pub fn reset_report_requests(&mut self) -> Drain<VTReport> {
         self.report_requests.drain()
     }
+
+    /// Current window title, empty if none was set yet
+    pub fn title(&self) -> &str { &self.title }
+
+    /// Current icon name, falls back to the window title if none was set
+    pub fn icon_name(&self) -> &str {
+        if self.icon_name.is_empty() { &self.title } else { &self.icon_name }
+    }
+
+    /// Returns whether the title or the icon name have changed since the last call
+    pub fn reset_title_changed(&mut self) -> bool {
+        mem::replace(&mut self.title_changed, false)
+    }
+
+    /// Current working directory of the shell, if the shell reports it
+    pub fn working_dir(&self) -> Option<&Path> { self.working_dir.as_deref() }
+
+    /// Host on which the working directory is located, may be empty if the shell didn't specify it
+    pub fn working_dir_host(&self) -> &str { &self.working_dir_host }
+
+    pub fn clipboard_policy(&self) -> ClipboardPolicy { self.clipboard_policy }
+
+    pub fn set_clipboard_policy(&mut self, policy: ClipboardPolicy) {
+        self.clipboard_policy = policy;
+    }
+
+    /// Whether window manipulation requests (`AppRequest::Window`) should be carried out,
+    /// by default they're passed on to the application to decide
+    pub fn window_policy(&self) -> Permission { self.window_policy }
+
+    pub fn set_window_policy(&mut self, policy: Permission) {
+        self.window_policy = policy;
+    }
+
+    /// Pending requests to store data into a selection.
+    /// Read requests are enqueued as `VTReport::Clipboard` along with other report requests,
+    /// they should be answered using `Term::clipboard_answer()`.
+    pub fn reset_clipboard_requests(&mut self) -> Drain<(VTSelection, String)> {
+        self.clipboard_requests.drain()
+    }
+
+    /// Clipboard requests held back by `Permission::Ask`, oldest first.
+    /// The application should ask the user about the first one and call `clipboard_confirm()`.
+    pub fn clipboard_pending(&self) -> &[ClipboardRequest] { &self.clipboard_pending }
+
+    /// Allow or deny the oldest clipboard request awaiting confirmation. Allowed requests are enqueued
+    /// like those allowed by the policy, see `reset_clipboard_requests()`.
+    pub fn clipboard_confirm(&mut self, allow: bool) {
+        if self.clipboard_pending.is_empty() {
+            return;
+        }
+
+        match (allow, self.clipboard_pending.remove(0)) {
+            (true, ClipboardRequest::Store(selection, data)) => self.clipboard_requests.push((selection, data)),
+            (true, ClipboardRequest::Load(selection)) => self.report_requests.push(VTReport::Clipboard(selection)),
+            (false, _) => {},
+        }
+    }
+
+    fn clipboard_hold(&mut self, request: ClipboardRequest) {
+        if self.clipboard_pending.len() < CLIPBOARD_PENDING_MAX {
+            self.clipboard_pending.push(request);
+        }
+    }
+
+    /// Pending palette changes and queries
+    pub fn reset_palette_requests(&mut self) -> Drain<PaletteRequest> {
+        self.palette_requests.drain()
+    }
+
+    pub fn user_var(&self, name: &str) -> Option<&str> { self.user_vars.get(name).map(|value| value.as_str()) }
+
+    pub fn user_vars(&self) -> &HashMap<String, String> { &self.user_vars }
+
+    /// Pending requests to the application, such as opening a new tab
+    pub fn reset_app_requests(&mut self) -> Drain<AppRequest> {
+        self.app_requests.drain()
+    }
+
+    /// Images transmitted with the kitty graphics protocol
+    pub fn image_store(&self) -> &ImageStore { &self.images }
+
+    /// Set the memory quota of the kitty graphics image store in bytes
+    pub fn set_image_quota(&mut self, quota: usize) {
+        self.images.set_quota(quota);
+    }
 }
 
 impl VTDispatch for TermState {
@@ -80,20 +340,100 @@ impl VTDispatch for TermState {
     fn screen_primary(&mut self) -> &mut Self::Screen { &mut self.screen_primary }
     fn screen_alternate(&mut self) -> &mut Self::Screen { &mut self.screen_alternate }
 
+    fn mode(&self) -> VTMode { self.mode }
+
     fn switch_screen(&mut self, screen: VTScreenChoice) {
         if screen != self.screen_current {
             self.screen_current = screen;
             self.screen_mut().set_dirty();
+            self.set_mode(VTMode::ALT_SCREEN, screen == VTScreenChoice::Alternate);
         }
     }
 
     fn set_mode(&mut self, mode: VTMode, enable: bool) {
+        if mode.contains(VTMode::SYNC_OUTPUT) {
+            match (enable, self.sync_start) {
+                // Re-enabling doesn't extend the timeout
+                (true, None) => self.sync_start = Some(Instant::now()),
+                (false, Some(_)) => {
+                    self.sync_start = None;
+                    self.sync_ended = true;
+                },
+                _ => {},
+            }
+        }
+
         self.mode.set(mode, enable);
         // Also copy the mode to the screens for easier access
         self.screen_primary.set_mode(mode, enable);
         self.screen_alternate.set_mode(mode, enable);
     }
 
+    fn full_reset(&mut self) {
+        self.mode = VTMode::default();
+        self.modes_saved.clear();
+        self.screen_current = VTScreenChoice::Primary;
+        self.screen_primary.reset();
+        self.screen_alternate.reset();
+        if self.reset_scrollback {
+            self.screen_primary.clear_scrollback();
+        }
+
+        self.title.clear();
+        self.icon_name.clear();
+        self.title_stack.clear();
+        self.title_changed = true;
+        self.clipboard_pending.clear();
+
+        self.palette_requests.push(PaletteRequest::Reset(None));
+        for &slot in [VTPaletteSlot::Foreground, VTPaletteSlot::Background, VTPaletteSlot::Cursor].iter() {
+            self.palette_requests.push(PaletteRequest::Reset(Some(slot)));
+        }
+
+        if self.sync_start.take().is_some() {
+            self.sync_ended = true;
+        }
+        self.dcs = None;
+        let quota = self.images.quota();
+        self.images = ImageStore::new();
+        self.images.set_quota(quota);
+    }
+
+    fn soft_reset(&mut self) {
+        let modes = [
+            (VTMode::CURSOR_VISIBLE, true),
+            (VTMode::INSERT, false),
+            (VTMode::ORIGIN, false),
+            (VTMode::WRAP, false),
+            (VTMode::APP_CURSOR_KEYS, false),
+        ];
+        for &(mode, enable) in modes.iter() {
+            self.set_mode(mode, enable);
+        }
+
+        self.screen_primary.soft_reset();
+        self.screen_alternate.soft_reset();
+    }
+
+    fn mode_save(&mut self, number: u32) {
+        if let Some(VTModeKind::Mode(mode)) = VTMode::from_number(true, number) {
+            let saved = self.modes_saved.entry(number).or_default();
+            if saved.len() >= MODE_SAVE_MAX {
+                saved.remove(0);
+            }
+            saved.push(self.mode.contains(mode));
+        }
+    }
+
+    fn mode_restore(&mut self, number: u32) -> Option<bool> {
+        self.modes_saved.get_mut(&number).and_then(|saved| saved.pop())
+    }
+
+    fn set_cursor_shape(&mut self, shape: VTCursorShape) {
+        self.screen_primary.set_cursor_shape(shape);
+        self.screen_alternate.set_cursor_shape(shape);
+    }
+
     fn report_request(&mut self, report: VTReport) {
         self.report_requests.push(report);
     }
@@ -102,8 +442,116 @@ impl VTDispatch for TermState {
         self.bell = true;
     }
 
-    // TP extensions:
-    // TODO
+    fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+        self.title_changed = true;
+    }
+
+    fn set_icon_name(&mut self, name: &str) {
+        self.icon_name = name.to_string();
+        self.title_changed = true;
+    }
+
+    fn title_push(&mut self, icon_name: bool, title: bool) {
+        if self.title_stack.len() >= TITLE_STACK_MAX {
+            self.title_stack.remove(0);
+        }
+
+        let entry = (
+            if icon_name { Some(self.icon_name.clone()) } else { None },
+            if title { Some(self.title.clone()) } else { None },
+        );
+        self.title_stack.push(entry);
+    }
+
+    fn title_pop(&mut self, icon_name: bool, title: bool) {
+        // Only the parts that were both saved and requested are restored
+        if let Some((icon_name_saved, title_saved)) = self.title_stack.pop() {
+            if let (true, Some(saved)) = (icon_name, icon_name_saved) { self.icon_name = saved; }
+            if let (true, Some(saved)) = (title, title_saved) { self.title = saved; }
+            self.title_changed = true;
+        }
+    }
+
+    fn window_request(&mut self, request: VTWindowRequest) {
+        if self.window_policy != Permission::Deny {
+            self.app_requests.push(AppRequest::Window(request));
+        }
+    }
+
+    fn set_working_dir(&mut self, host: &str, path: &Path) {
+        self.working_dir = Some(path.to_path_buf());
+        self.working_dir_host = host.to_string();
+    }
+
+    fn clipboard_store(&mut self, selection: VTSelection, data: &str) {
+        match self.clipboard_policy.write {
+            Permission::Allow => self.clipboard_requests.push((selection, data.to_string())),
+            Permission::Ask => self.clipboard_hold(ClipboardRequest::Store(selection, data.to_string())),
+            Permission::Deny => {},
+        }
+    }
+
+    fn clipboard_load(&mut self, selection: VTSelection) {
+        match self.clipboard_policy.read {
+            Permission::Allow => self.report_requests.push(VTReport::Clipboard(selection)),
+            Permission::Ask => self.clipboard_hold(ClipboardRequest::Load(selection)),
+            Permission::Deny => {},
+        }
+    }
+
+    fn palette_set(&mut self, slot: VTPaletteSlot, rgb: (u8, u8, u8)) {
+        self.palette_requests.push(PaletteRequest::Set(slot, rgb));
+    }
+
+    fn palette_reset(&mut self, slot: Option<VTPaletteSlot>) {
+        self.palette_requests.push(PaletteRequest::Reset(slot));
+    }
+
+    fn palette_query(&mut self, slot: VTPaletteSlot) {
+        self.palette_requests.push(PaletteRequest::Query(slot));
+    }
+
+    fn dcs_hook(&mut self, private: u8, interm: u8, params: &[i32], action: u8) {
+        self.dcs = dcs::handler(private, interm, params, action);
+    }
+
+    fn dcs_put(&mut self, byte: u8) {
+        if let Some(handler) = self.dcs.as_mut() {
+            handler.put(byte);
+        }
+    }
+
+    fn dcs_unhook(&mut self, complete: bool) {
+        match self.dcs.take() {
+            Some(handler) if complete => handler.unhook(self),
+            _ => {},
+        }
+    }
+
+    fn kitty_graphics(&mut self, data: &[u8]) {
+        kitty::command(self, data);
+    }
+
+    fn tp_set_user_var(&mut self, name: &str, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                let fits = self.user_vars.len() < USER_VARS_MAX || self.user_vars.contains_key(name);
+                if fits && name.len() <= USER_VAR_NAME_MAX && value.len() <= USER_VAR_VALUE_MAX {
+                    self.user_vars.insert(name.to_string(), value.to_string());
+                }
+            },
+            None => { self.user_vars.remove(name); },
+        }
+    }
+
+    fn tp_bookmark(&mut self, label: &str) {
+        self.screen_mut().bookmark(label);
+    }
+
+    fn tp_new_tab(&mut self, working_dir: Option<&Path>) {
+        self.app_requests.push(AppRequest::NewTab(working_dir.map(|dir| dir.to_path_buf())));
+    }
 }
 
 #[derive(Debug)]
@@ -133,6 +581,32 @@ impl Term {
     pub fn report_answer(&self, report: VTReport, buffer: &mut [u8]) -> Result<usize, ()> {
         self.input.report_answer(self.screen(), report, buffer)
     }
+
+    /// List shell command blocks (as marked by OSC 133) in the primary screen and its scrollback, oldest first
+    pub fn command_blocks(&self) -> Vec<CommandBlock> {
+        blocks::command_blocks(&self.state.screen_primary)
+    }
+
+    /// Get the output text of a command block obtained from `command_blocks()`
+    pub fn command_output(&self, block: &CommandBlock) -> String {
+        blocks::command_output(&self.state.screen_primary, block)
+    }
+
+    /// List bookmarked lines (see `VTDispatch::tp_bookmark()`) in the primary screen and its scrollback,
+    /// as history line indices along with the labels, oldest first
+    pub fn bookmarks(&self) -> Vec<(usize, &str)> {
+        self.state.screen_primary.bookmarks()
+    }
+
+    /// Answer a `VTReport::Clipboard` request with the content of the selection
+    pub fn clipboard_answer(&self, selection: VTSelection, data: &str, buffer: &mut [u8]) -> Result<usize, ()> {
+        self.input.clipboard_answer(selection, data, buffer)
+    }
+
+    /// Answer a `PaletteRequest::Query` with the current color of the palette slot
+    pub fn color_answer(&self, slot: VTPaletteSlot, rgb: (u8, u8, u8), buffer: &mut [u8]) -> Result<usize, ()> {
+        self.input.color_answer(slot, rgb, buffer)
+    }
 }
 
 impl ops::Deref for Term {
@@ -153,11 +627,618 @@ impl ops::DerefMut for Term {
 
 #[cfg(test)]
 mod tests {
-// XXX
+use super::*;
 
 #[test]
 fn it_works() {
     assert_eq!(2 + 2, 4);
 }
 
+#[test]
+fn clipboard_policy() {
+    let mut term = Term::new();
+    term.set_clipboard_policy(ClipboardPolicy { read: Permission::Allow, write: Permission::Allow });
+    term.write(b"\x1b]52;c;Zm9v\x07\x1b]52;p;?\x07");
+    assert_eq!(term.reset_clipboard_requests().collect::<Vec<_>>(), vec![(VTSelection::Clipboard, "foo".to_string())]);
+    assert_eq!(term.reset_report_requests().collect::<Vec<_>>(), vec![VTReport::Clipboard(VTSelection::Primary)]);
+    assert!(term.clipboard_pending().is_empty());
+
+    let mut buffer = [0u8; 32];
+    let size = term.clipboard_answer(VTSelection::Primary, "bar", &mut buffer).unwrap();
+    assert_eq!(&buffer[..size], b"\x1b]52;p;YmFy\x1b\\");
+
+    term.set_clipboard_policy(ClipboardPolicy { read: Permission::Deny, write: Permission::Deny });
+    term.write(b"\x1b]52;c;Zm9v\x07\x1b]52;p;?\x07");
+    assert_eq!(term.reset_clipboard_requests().count(), 0);
+    assert_eq!(term.reset_report_requests().count(), 0);
+    assert!(term.clipboard_pending().is_empty());
+}
+
+#[test]
+fn clipboard_ask() {
+    let mut term = Term::new();
+    term.set_clipboard_policy(ClipboardPolicy { read: Permission::Ask, write: Permission::Ask });
+    term.write(b"\x1b]52;c;Zm9v\x07\x1b]52;p;?\x07");
+    assert_eq!(term.reset_clipboard_requests().count(), 0);
+    assert_eq!(term.reset_report_requests().count(), 0);
+    assert_eq!(term.clipboard_pending(), &[
+        ClipboardRequest::Store(VTSelection::Clipboard, "foo".to_string()),
+        ClipboardRequest::Load(VTSelection::Primary),
+    ]);
+
+    term.clipboard_confirm(false);
+    assert_eq!(term.reset_clipboard_requests().count(), 0);
+    term.clipboard_confirm(true);
+    assert_eq!(term.reset_report_requests().collect::<Vec<_>>(), vec![VTReport::Clipboard(VTSelection::Primary)]);
+    assert_eq!(term.report_answer(VTReport::Clipboard(VTSelection::Primary), &mut [0; 64]), Err(()));
+    assert!(term.clipboard_pending().is_empty());
+
+    for _ in 0 .. CLIPBOARD_PENDING_MAX + 2 {
+        term.write(b"\x1b]52;c;Zm9v\x07");
+    }
+    assert_eq!(term.clipboard_pending().len(), CLIPBOARD_PENDING_MAX);
+}
+
+#[test]
+fn command_blocks() {
+    let mut term = Term::new();
+    term.write(b"\x1b]133;A\x07$ \x1b]133;B\x07ls\r\n\x1b]133;C\x07");
+    term.write("file1\r\nfile2 \u{e1}\r\n\u{6f22}\u{5b57}x\r\n\x1b]133;D;0\x07".as_bytes());
+    term.write(b"\x1b]133;A\x07$ \x1b]133;B\x07seq 50\r\n\x1b]133;C\x07");
+    for i in 1 .. 51 {
+        term.write(format!("{}\r\n", i).as_bytes());
+    }
+    term.write(b"\x1b]133;D;1\x07\x1b]133;A\x07$ ");
+
+    let blocks = term.command_blocks();
+    assert_eq!(blocks.len(), 3);
+    assert_eq!(blocks[0].prompt, BlockPos { line: 0, x: 0 });
+    assert_eq!(blocks[0].command, Some(BlockPos { line: 0, x: 2 }));
+    assert_eq!(blocks[0].exit_code, Some(0));
+    assert!(blocks[0].duration.is_some());
+    assert_eq!(term.command_output(&blocks[0]), "file1\nfile2 \u{e1}\n\u{6f22}\u{5b57}x");
+
+    // The second block has scrolled partially into scrollback
+    assert!(term.screen_primary.scrollback().unwrap().len() > 0);
+    assert_eq!(blocks[1].exit_code, Some(1));
+    let expected: Vec<_> = (1 .. 51).map(|i| i.to_string()).collect();
+    assert_eq!(term.command_output(&blocks[1]), expected.join("\n"));
+
+    assert_eq!(blocks[2].end, None);
+    assert_eq!(term.command_output(&blocks[2]), "");
+}
+
+fn answers(term: &mut Term) -> Vec<String> {
+    let reports: Vec<_> = term.reset_report_requests().collect();
+    reports.into_iter().map(|report| {
+        let mut buffer = [0u8; 256];
+        let size = term.report_answer(report, &mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..size]).into_owned()
+    }).collect()
+}
+
+#[test]
+fn mouse_input() {
+    let mut term = Term::new();
+    let mut buffer = [0; 64];
+    let mut input = |term: &Term, action, button, cell: (u32, u32)| {
+        let event = MouseEvent { action, button, modifier: Modifier::CONTROL, cell, pixel: (cell.0 * 10, cell.1 * 20) };
+        let size = term.input(InputData::Mouse(event), &mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..size]).into_owned()
+    };
+    use MouseAction::*;
+
+    assert_eq!(input(&term, Press, MouseButton::Left, (0, 0)), "");
+
+    term.write(b"\x1b[?9h");
+    assert_eq!(input(&term, Press, MouseButton::Left, (1, 2)), "\x1b[M \"#");
+    assert_eq!(input(&term, Release, MouseButton::Left, (1, 2)), "");
+
+    term.write(b"\x1b[?1000h");
+    assert_eq!(input(&term, Release, MouseButton::Left, (1, 2)), "\x1b[M3\"#");
+    assert_eq!(input(&term, Motion, MouseButton::Left, (1, 2)), "");
+    assert_eq!(input(&term, Press, MouseButton::Left, (300, 2)), "");
+
+    term.write(b"\x1b[?1002h\x1b[?1006h");
+    assert_eq!(input(&term, Motion, MouseButton::Left, (1, 2)), "\x1b[<48;2;3M");
+    assert_eq!(input(&term, Motion, MouseButton::None, (1, 2)), "");
+    assert_eq!(input(&term, Release, MouseButton::Right, (300, 2)), "\x1b[<18;301;3m");
+    assert_eq!(input(&term, Release, MouseButton::WheelUp, (1, 2)), "");
+
+    term.write(b"\x1b[?1003h\x1b[?1015h");
+    assert_eq!(input(&term, Motion, MouseButton::None, (1, 2)), "\x1b[83;2;3M");
+
+    term.write(b"\x1b[?1016h");
+    assert_eq!(input(&term, Press, MouseButton::WheelDown, (1, 2)), "\x1b[<81;11;41M");
+
+    term.write(b"\x1b[?1005h");
+    assert_eq!(input(&term, Press, MouseButton::Left, (300, 2)), "\x1b[M0\u{14d}#");
+
+    term.write(b"\x1b[?1003l");
+    assert_eq!(input(&term, Press, MouseButton::Left, (1, 2)), "");
+}
+
+#[test]
+fn bracketed_paste() {
+    let mut term = Term::new();
+    let mut buffer = [0; 64];
+    let mut paste = |term: &Term, text| {
+        let size = term.input(InputData::Paste(text), &mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..size]).into_owned()
+    };
+
+    assert_eq!(paste(&term, "ls\r\nrm -rf ~\n"), "ls\rrm -rf ~\r");
+
+    term.write(b"\x1b[?2004h");
+    assert_eq!(paste(&term, "a\x1b[201~\u{9b}b\tc\x7f"), "\x1b[200~ab\tc\x1b[201~");
+
+    term.write(b"\x1b[?2004l");
+    assert_eq!(paste(&term, "echo \x03x"), "echo x");
+}
+
+#[test]
+fn focus_events() {
+    let mut term = Term::new();
+    let mut buffer = [0; 8];
+
+    assert_eq!(term.input(InputData::FocusIn, &mut buffer), Ok(0));
+    term.write(b"\x1b[?1004h");
+    assert_eq!(term.input(InputData::FocusIn, &mut buffer), Ok(3));
+    assert_eq!(&buffer[..3], b"\x1b[I");
+    assert_eq!(term.input(InputData::FocusOut, &mut buffer), Ok(3));
+    assert_eq!(&buffer[..3], b"\x1b[O");
+    term.write(b"\x1b[?1004l");
+    assert_eq!(term.input(InputData::FocusOut, &mut buffer), Ok(0));
+}
+
+#[test]
+fn decrqss() {
+    let mut term = Term::new();
+    term.write(b"\x1b[1;4;31;48;5;200m\x1b[5;20r");
+    term.write(b"\x1bP$qm\x1b\\\x1bP$qr\x1b\\\x1bP$qx\x1b\\\x1bP$qm\x18");
+    assert_eq!(answers(&mut term), vec![
+        "\x1bP1$r0;1;4;31;48;5;200m\x1b\\",
+        "\x1bP1$r5;20r\x1b\\",
+        "\x1bP0$r\x1b\\",
+    ]);
+}
+
+#[test]
+fn window_ops() {
+    let mut term = Term::new();
+    term.screen_resize(100, 30);
+    term.set_cell_size(8, 16);
+    term.write(b"\x1b[14t\x1b[16t\x1b[18t");
+    assert_eq!(answers(&mut term), vec![ "\x1b[4;480;800t", "\x1b[6;16;8t", "\x1b[8;30;100t" ]);
+
+    term.write(b"\x1b[5t");
+    assert_eq!(term.reset_app_requests().collect::<Vec<_>>(), vec![AppRequest::Window(VTWindowRequest::Raise(true))]);
+    term.set_window_policy(Permission::Deny);
+    term.write(b"\x1b[5t");
+    assert_eq!(term.reset_app_requests().count(), 0);
+}
+
+#[test]
+fn title_stack() {
+    let mut term = Term::new();
+    term.write(b"\x1b]0;one\x07\x1b[22t\x1b]0;two\x07\x1b[22;2t\x1b]0;three\x07");
+    term.write(b"\x1b[23;2t");
+    assert_eq!((term.icon_name(), term.title()), ("three", "two"));
+    term.write(b"\x1b]1;four\x07\x1b[22;2t\x1b[23;0t");
+    assert_eq!((term.icon_name(), term.title()), ("four", "two"));
+    term.write(b"\x1b[23;0t");
+    assert_eq!((term.icon_name(), term.title()), ("one", "one"));
+    term.write(b"\x1b[23t");
+    assert_eq!((term.icon_name(), term.title()), ("one", "one"));
+
+    for _ in 0 .. TITLE_STACK_MAX + 5 {
+        term.write(b"\x1b[22t");
+    }
+    assert_eq!(term.title_stack.len(), TITLE_STACK_MAX);
+}
+
+#[test]
+fn full_reset() {
+    let mut term = Term::new();
+    term.screen_resize(20, 10);
+    for _ in 0 .. 15 {
+        term.write(b"line\r\n");
+    }
+    term.write(b"\x1b]0;title\x07\x1b[22t\x1b[?2004;6h\x1b[3g\x1b[5;8r\x1b[1;31m\x1b(0\x1b7\x1b[6 q\x1b[?1049h");
+    term.write(b"\x1b]4;1;rgb:ff/00/00\x07");
+    term.reset_palette_requests().count();
+    let sb_len = term.screen_primary.scrollback().unwrap().len();
+
+    term.write(b"\x1bc");
+    assert_eq!(term.screen_current, VTScreenChoice::Primary);
+    assert_eq!(term.mode(), VTMode::default());
+    assert_eq!(term.screen().mode(), VTMode::default());
+    assert_eq!((term.title(), term.icon_name()), ("", ""));
+    assert!(term.title_stack.is_empty());
+    assert_eq!(term.screen().cursor(), (1, 1));
+    assert_eq!(term.screen().current_style(), Style::default());
+    assert_eq!(term.screen().scroll_region(), (1, 10));
+    assert_eq!(term.screen().cursor_shape(), VTCursorShape::Block);
+    assert_eq!(term.screen().history_text(sb_len, 0, 20), "");
+    assert_eq!(term.screen_primary.scrollback().unwrap().len(), sb_len);
+    assert_eq!(term.reset_palette_requests().next(), Some(PaletteRequest::Reset(None)));
+
+    // Tab stops and charsets are back to defaults, as is the saved cursor
+    term.write(b"\tq\x1b8x");
+    assert_eq!(term.screen().history_text(sb_len, 0, 20), "x       q");
+
+    term.set_reset_scrollback(true);
+    term.write(b"\x1bc");
+    assert_eq!(term.screen_primary.scrollback().unwrap().len(), 0);
+}
+
+#[test]
+fn soft_reset() {
+    let mut term = Term::new();
+    term.screen_resize(20, 10);
+    term.write(b"hello\x1b[?25l\x1b[4h\x1b[?6h\x1b[3;6r\x1b[?7h\x1b[?2004h\x1b[1;4m\x1b(0\x1b[4;4H\x1b7");
+    term.write(b"\x1b[!p");
+    assert_eq!(term.screen().cursor(), (4, 6));
+    assert!(term.mode().contains(VTMode::CURSOR_VISIBLE | VTMode::BRACKETED_PASTE));
+    assert!(!term.mode().intersects(VTMode::WRAP | VTMode::INSERT | VTMode::ORIGIN));
+    assert_eq!(term.screen().current_style(), Style::default());
+    assert_eq!(term.screen().scroll_region(), (1, 10));
+
+    // The content is kept, the saved cursor goes to the home position
+    term.write(b"q\x1b8");
+    assert_eq!(term.screen().cursor(), (1, 1));
+    assert_eq!(term.screen().history_text(term.screen().history_len() - 10, 0, 20), "hello");
+    assert_eq!(term.screen().history_text(term.screen().history_len() - 5, 0, 20), "   q");
+}
+
+#[test]
+fn line_editing() {
+    let mut term = Term::new();
+    term.screen_resize(20, 10);
+    let line = |term: &Term, y: usize| term.screen().history_text(term.screen().history_len() - 10 + y, 0, 20);
+
+    term.write(b"abcdef\r\x1b[2C\x1b[2@");
+    assert_eq!(line(&term, 0), "ab  cdef");
+    term.write(b"\x1b[3P");
+    assert_eq!(line(&term, 0), "abdef");
+    term.write(b"\x1b[X");
+    assert_eq!(line(&term, 0), "ab ef");
+    term.write(b"\r\x1b[4hXY\x1b[4l");
+    assert_eq!(line(&term, 0), "XYab ef");
+    term.write(b"\x1b[3b");
+    assert_eq!(line(&term, 0), "XYYYYef");
+
+    // Wide characters aren't split in half
+    term.write("\r\na\u{4e2d}b\x1b[3G\x1b[@".as_bytes());
+    assert_eq!(line(&term, 1), "a   b");
+    term.write("\r\nx\x1b[19G\u{4e2d}\r\x1b[@".as_bytes());
+    assert_eq!(line(&term, 2), " x");
+    term.write("\r\na\u{4e2d}b\x1b[3G\x1b[P".as_bytes());
+    assert_eq!(line(&term, 3), "a b");
+}
+
+#[test]
+fn margins() {
+    let mut term = Term::new();
+    term.screen_resize(20, 10);
+    let line = |term: &Term, y: usize| term.screen().history_text(term.screen().history_len() - 10 + y, 0, 20);
+
+    term.write(b"\x1b[?69h\x1b[5;10s");
+    assert_eq!(term.screen().margins(), (5, 10));
+    assert_eq!(term.screen().cursor(), (1, 1));
+
+    // Wrapping, inserting and deleting stay within the margins
+    term.write(b"\x1b[1;13HZ\x1b[1;5Habcdefghij");
+    assert_eq!(line(&term, 0), "    abcdef  Z");
+    assert_eq!(line(&term, 1), "    ghij");
+    term.write(b"\x1b[1;5H\x1b[2@");
+    assert_eq!(line(&term, 0), "      abcd  Z");
+    term.write(b"\x1b[3P");
+    assert_eq!(line(&term, 0), "    bcd     Z");
+
+    // So does scrolling
+    term.write(b"\x1b[2;1HX\x1b[S");
+    assert_eq!(line(&term, 0), "    ghij    Z");
+    assert_eq!(line(&term, 1), "X");
+
+    // Origin mode is relative to the margins, carriage return goes to the left margin
+    term.write(b"\x1b[?6h\x1b[3;6r");
+    assert_eq!(term.screen().cursor(), (5, 3));
+    term.write(b"\x1b[2;3H");
+    assert_eq!(term.screen().cursor(), (7, 4));
+    term.write(b"\x1b[?6l\x1b[3;8Hq\r");
+    assert_eq!(term.screen().cursor(), (5, 3));
+    term.write(b"\x1b[3;2H\r");
+    assert_eq!(term.screen().cursor(), (1, 3));
+
+    term.write(b"\x1b[?69l");
+    assert_eq!(term.screen().margins(), (1, 20));
+}
+
+#[test]
+fn line_size() {
+    let mut term = Term::new();
+    term.screen_resize(20, 10);
+    let line = |term: &Term, y: usize| term.screen().history_len() - 10 + y;
+
+    // The right half of the line is lost, and the cursor is kept in the left half
+    term.write(b"0123456789abcdefghij\x1b#6");
+    assert_eq!(term.screen().history_line_size(line(&term, 0)), VTLineSize::DoubleWidth);
+    assert_eq!(term.screen().history_text(line(&term, 0), 0, 20), "0123456789");
+    assert_eq!(term.screen().cursor(), (10, 1));
+    term.write(b"\x1b[15GX");
+    assert_eq!(term.screen().history_text(line(&term, 0), 0, 20), "012345678X");
+
+    // Wrapping happens at the half of the line
+    term.write(b"\r\n\x1b#3abcdefghijkl\x1b[3;15H\x1b#4");
+    assert_eq!(term.screen().history_line_size(line(&term, 1)), VTLineSize::DoubleTop);
+    assert_eq!(term.screen().history_text(line(&term, 1), 0, 20), "abcdefghij");
+    assert_eq!(term.screen().history_text(line(&term, 2), 0, 20), "kl");
+    assert_eq!(term.screen().history_line_size(line(&term, 2)), VTLineSize::DoubleBottom);
+    term.write(b"\x1b[4;15H\x1b#4");
+    assert_eq!(term.screen().cursor(), (10, 4));
+    term.write(b"\x1b#5");
+    assert_eq!(term.screen().history_line_size(line(&term, 3)), VTLineSize::Single);
+
+    // The size is kept in the scrollback
+    term.write(b"\x1b[10;1H\n\n\n\n");
+    assert_eq!(term.screen().history_line_size(line(&term, 0) - 4), VTLineSize::DoubleWidth);
+    assert_eq!(term.screen().history_line_size(line(&term, 0) - 3), VTLineSize::DoubleTop);
+    assert_eq!(term.screen().history_line_size(line(&term, 0) - 2), VTLineSize::DoubleBottom);
+    assert_eq!(term.screen().history_line_size(line(&term, 0) - 1), VTLineSize::Single);
+}
+
+#[test]
+fn charsets() {
+    let mut term = Term::new();
+    term.screen_resize(20, 10);
+    let line = |term: &mut Term, data: &[u8]| {
+        term.write(b"\x1b[2K\r");
+        term.write(data);
+        term.screen().history_text(term.screen().history_len() - 10, 0, 20)
+    };
+
+    assert_eq!(line(&mut term, b"\x1b(0lx\x1b[2b\x1b(Bx"), "\u{250c}\u{2502}\u{2502}\u{2502}x");
+    assert_eq!(line(&mut term, b"\x1b)A\x0e#\x0f#"), "\u{00a3}#");
+    assert_eq!(line(&mut term, b"\x1b(K[]{}~\x1b(B[]"), "\u{00c4}\u{00dc}\u{00e4}\u{00fc}\u{00df}[]");
+
+    // Single shifts only apply to the next character
+    assert_eq!(line(&mut term, b"\x1b*%5\x1bNWW"), "\u{0152}W");
+    // 96-character sets
+    assert_eq!(line(&mut term, b"\x1b-A\x0eA\x0fA"), "\u{00c1}A");
+    // Text decoded from UTF-8 isn't affected by the charsets, whether invoked into GR or not
+    assert_eq!(line(&mut term, "\x1b*0\u{00e9}\x1b}\u{00e9}\x1b+>\x1b|\u{00e4}\x1bN\u{00e4}".as_bytes()), "\u{00e9}\u{00e9}\u{00e4}\u{00e4}");
+}
+
+#[test]
+fn decrqm() {
+    let mut term = Term::new();
+    term.write(b"\x1b[?1049h\x1b[4h");
+    term.write(b"\x1b[?1049$p\x1b[?7$p\x1b[?1000$p\x1b[?3$p\x1b[?2$p\x1b[?1234$p\x1b[4$p\x1b[20$p");
+    assert_eq!(answers(&mut term), vec![
+        "\x1b[?1049;1$y", "\x1b[?7;1$y", "\x1b[?1000;2$y", "\x1b[?3;4$y",
+        "\x1b[?2;3$y", "\x1b[?1234;0$y", "\x1b[4;1$y", "\x1b[20;2$y",
+    ]);
+}
+
+#[test]
+fn mode_save_restore() {
+    let mut term = Term::new();
+
+    // The saved states are shared by both screens and nest
+    term.write(b"\x1b[?2004s\x1b[?2004h\x1b[?1049h\x1b[?2004s\x1b[?2004l");
+    assert!(!term.mode().contains(VTMode::BRACKETED_PASTE));
+    term.write(b"\x1b[?1049l\x1b[?2004r");
+    assert!(term.mode().contains(VTMode::BRACKETED_PASTE));
+    term.write(b"\x1b[?2004r");
+    assert!(!term.mode().contains(VTMode::BRACKETED_PASTE));
+    term.write(b"\x1b[?2004h\x1b[?2004r");
+    assert!(term.mode().contains(VTMode::BRACKETED_PASTE));
+
+    // Restoring goes through DECSET, so mouse tracking modes still replace each other
+    term.write(b"\x1b[?1002s\x1b[?1002h\x1b[?1000h\x1b[?1002r");
+    assert!(!term.mode().intersects(VTMode::MOUSE_TRACKING));
+
+    term.write(b"\x1b[?1049s\x1b[?1049h\x1b[?1049r");
+    assert!(!term.mode().contains(VTMode::ALT_SCREEN));
+    assert_eq!(term.screen_current, VTScreenChoice::Primary);
+}
+
+#[test]
+fn sync_output() {
+    let mut term = Term::new();
+    term.write(b"\x1b[?2026$p");
+    assert_eq!(answers(&mut term), vec![ "\x1b[?2026;2$y" ]);
+
+    term.write(b"\x1b[?2026h\x1b[?2026$p\x1b[?1$p");
+    assert!(term.sync_update());
+    assert!(term.sync_deadline().unwrap() <= Instant::now() + SYNC_TIMEOUT);
+    assert!(!term.reset_sync_ended());
+    assert_eq!(answers(&mut term), vec![ "\x1b[?2026;1$y", "\x1b[?1;2$y" ]);
+
+    term.write(b"\x1b[?2026l");
+    assert!(!term.sync_update());
+    assert!(term.reset_sync_ended());
+    assert!(!term.reset_sync_ended());
+
+    term.sync_start = Some(Instant::now() - SYNC_TIMEOUT);
+    assert!(!term.sync_update());
+}
+
+#[test]
+fn cursor_style() {
+    let mut term = Term::new();
+    assert_eq!((term.screen().cursor_shape(), term.screen().cursor_blink()), (VTCursorShape::Block, true));
+
+    // Cursor save & restore leaves the cursor style alone
+    term.write(b"\x1b7\x1b[4 q\x1b[?25l\x1b8\x1b[?1049h");
+    assert_eq!((term.screen().cursor_shape(), term.screen().cursor_blink()), (VTCursorShape::Underline, false));
+    assert!(!term.screen().cursor_visible());
+
+    term.write(b"\x1b[5 q\x1bP$q q\x1b\\");
+    assert_eq!(answers(&mut term), vec![ "\x1bP1$r5 q\x1b\\" ]);
+    term.write(b"\x1b[?12l\x1bP$q q\x1b\\");
+    assert_eq!(answers(&mut term), vec![ "\x1bP1$r6 q\x1b\\" ]);
+}
+
+#[test]
+fn xtgettcap() {
+    let mut term = Term::new();
+    // TN;Tc;xx
+    term.write(b"\x1bP+q544E;5463;7878\x1b\\");
+    assert_eq!(answers(&mut term), vec![
+        "\x1bP1+r544E=787465726D2D323536636F6C6F72\x1b\\",
+        "\x1bP1+r5463\x1b\\",
+        "\x1bP0+r7878\x1b\\",
+    ]);
+}
+
+#[test]
+fn sixel_image() {
+    let mut term = Term::new();
+    term.screen_resize(20, 10);
+    term.set_cell_size(10, 20);
+
+    // A 15x30 pixel image takes up 2x2 cells, the cursor ends up below it
+    term.write(b"\x1b[2;3H\x1bP0;1q\"1;1;15;30#1!15~\x1b\\");
+    let images: Vec<_> = term.screen().images().map(|(y, img)| (y, img.x, img.cols, img.row)).collect();
+    assert_eq!(term.screen().images().next().unwrap().1.placement.size, (15, 30));
+    assert_eq!(images, vec![(1, 2, 2, 0), (2, 2, 2, 1)]);
+    assert_eq!(term.screen().cursor(), (3, 4));
+
+    // Images scroll with the text
+    term.write(b"\x1b[10H\n");
+    let images: Vec<_> = term.screen().images().map(|(y, img)| (y, img.row)).collect();
+    assert_eq!(images, vec![(0, 0), (1, 1)]);
+
+    // Erasing drops image slices
+    term.write(b"\x1b[2;1H\x1b[K");
+    assert_eq!(term.screen().images().count(), 1);
+
+    // Scrolling into the scrollback drops them too
+    term.write(b"\x1b[10H\n");
+    assert_eq!(term.screen().images().count(), 0);
+}
+
+#[test]
+fn kitty_graphics() {
+    let mut term = Term::new();
+    term.screen_resize(20, 10);
+    term.set_cell_size(10, 20);
+
+    // Chunked direct transmission of a 2x1 RGB image, then a placement over 3x2 cells
+    let data = base64::encode(&[255, 0, 0, 0, 255, 0]);
+    let (chunk1, chunk2) = data.split_at(4);
+    term.write(format!("\x1b_Gi=7,f=24,s=2,v=1,m=1;{}\x1b\\\x1b_Gm=0;{}\x1b\\", chunk1, chunk2).as_bytes());
+    assert_eq!(answers(&mut term), vec!["\x1b_Gi=7;OK\x1b\\"]);
+    assert_eq!(term.image_store().get(7).unwrap().pixel(1, 0), Some([0, 255, 0, 255]));
+
+    term.write(b"\x1b[2;2H\x1b_Ga=p,i=7,p=1,c=3,r=2,z=-1,q=1\x1b\\");
+    let images: Vec<_> = term.screen().images().map(|(y, img)| (y, img.x, img.cols, img.row, img.placement.z)).collect();
+    assert_eq!(images, vec![(1, 1, 3, 0, -1), (2, 1, 3, 1, -1)]);
+    assert_eq!(term.screen().cursor(), (5, 3));
+
+    // Placing again with the same placement id moves the placement
+    term.write(b"\x1b[5;1H\x1b_Ga=p,i=7,p=1,C=1,q=1\x1b\\");
+    let images: Vec<_> = term.screen().images().map(|(y, img)| (y, img.x, img.placement.size)).collect();
+    assert_eq!(images, vec![(4, 0, (2, 1))]);
+    assert_eq!(term.screen().cursor(), (1, 5));
+
+    // PNG with an image number, the terminal assigns an id
+    let mut png_data = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut png_data, 1, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.write_header().unwrap().write_image_data(&[128]).unwrap();
+    }
+    term.write(format!("\x1b_Ga=T,I=3,f=100;{}\x1b\\", base64::encode(&png_data)).as_bytes());
+    let answer = answers(&mut term);
+    assert!(answer[0].starts_with("\x1b_Gi=") && answer[0].ends_with(",I=3;OK\x1b\\"));
+    assert_eq!(term.screen().images().count(), 2);
+
+    // Errors
+    term.write(b"\x1b_Ga=p,i=99\x1b\\\x1b_Gi=8,f=32,s=10,v=10;AAAA\x1b\\\x1b_Gi=9,f=100,q=2;AAAA\x1b\\");
+    assert_eq!(answers(&mut term), vec![
+        "\x1b_Gi=99;ENOENT:image not found\x1b\\",
+        "\x1b_Gi=8;ENODATA:insufficient image data\x1b\\",
+    ]);
+
+    // Delete by number keeps the data, delete by id frees it
+    term.write(b"\x1b_Ga=d,d=n,I=3\x1b\\");
+    assert_eq!(term.screen().images().count(), 1);
+    assert_eq!(term.image_store().len(), 2);
+    term.write(b"\x1b_Ga=d,d=I,i=7\x1b\\");
+    assert_eq!(term.screen().images().count(), 0);
+    assert_eq!(term.image_store().len(), 1);
+}
+
+#[test]
+fn kitty_graphics_file() {
+    use std::{env, fs};
+
+    let path = env::temp_dir().join(format!("tty-graphics-protocol-test-{}", std::process::id()));
+    fs::write(&path, [0, 0, 0, 1, 2, 3, 4, 5]).unwrap();
+
+    let mut term = Term::new();
+    let path_b64 = base64::encode(path.to_string_lossy().as_bytes());
+    term.write(format!("\x1b_Gi=1,f=24,s=1,v=1,t=t,O=3,S=3;{}\x1b\\", path_b64).as_bytes());
+    assert_eq!(answers(&mut term), vec!["\x1b_Gi=1;OK\x1b\\"]);
+    assert_eq!(term.image_store().get(1).unwrap().pixel(0, 0), Some([1, 2, 3, 255]));
+    assert!(!path.exists());
+
+    term.write(format!("\x1b_Gi=2,t=f;{}\x1b\\", path_b64).as_bytes());
+    assert!(answers(&mut term)[0].starts_with("\x1b_Gi=2;ENOENT:"));
+    term.write(format!("\x1b_Gi=3,t=f;{}\x1b\\", base64::encode(b"/proc/self/environ")).as_bytes());
+    assert!(answers(&mut term)[0].starts_with("\x1b_Gi=3;EPERM:"));
+    term.write(format!("\x1b_Gi=4,t=f;{}\x1b\\", base64::encode(b"/tmp/../proc/self/environ")).as_bytes());
+    assert!(answers(&mut term)[0].starts_with("\x1b_Gi=4;EPERM:"));
+
+    // `..` is resolved before deciding whether to delete, only files directly in a temp directory are
+    let dir = env::temp_dir().join(format!("tty-graphics-protocol-dir-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    let victim = dir.join("sub").join("victim");
+    fs::write(&victim, [0, 0, 0, 1, 2, 3]).unwrap();
+    let path_b64 = base64::encode(dir.join("sub/../sub/victim").to_string_lossy().as_bytes());
+    term.write(format!("\x1b_Gi=5,f=24,s=1,v=1,t=t,O=3,S=3;{}\x1b\\", path_b64).as_bytes());
+    assert_eq!(answers(&mut term), vec!["\x1b_Gi=5;OK\x1b\\"]);
+    assert!(victim.exists());
+
+    fs::write(&path, [0, 0, 0, 1, 2, 3]).unwrap();
+    let path_b64 = base64::encode(dir.join("..").join(path.file_name().unwrap()).to_string_lossy().as_bytes());
+    term.write(format!("\x1b_Gi=6,f=24,s=1,v=1,t=t,O=3,S=3;{}\x1b\\", path_b64).as_bytes());
+    assert_eq!(answers(&mut term), vec!["\x1b_Gi=6;OK\x1b\\"]);
+    assert!(!path.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn apc_tp() {
+    let mut term = Term::new();
+    term.screen_resize(20, 5);
+
+    term.write(b"\x1b_TPSetUserVar;name=branch;value=master\x1b\\\x1b_TPSetUserVar;name=x;value=1\x1b\\");
+    term.write(b"\x1b_TPSetUserVar;name=x\x1b\\");
+    assert_eq!(term.user_var("branch"), Some("master"));
+    assert_eq!(term.user_vars().len(), 1);
+
+    // Bookmarks travel into the scrollback along with their lines
+    term.write(b"one\x1b_TPBookmark;label=first\x1b\\\r\ntwo\r\nthree\x1b_TPBookmark\x1b\\\r\n\r\n\r\n\r\n");
+    assert_eq!(term.bookmarks(), vec![(0, "first"), (2, "")]);
+    assert_eq!(term.screen().history_text(0, 0, 10), "one");
+
+    term.write(b"\x1b_TPNewTab;cwd=/tmp\x1b\\");
+    assert_eq!(term.reset_app_requests().collect::<Vec<_>>(), vec![AppRequest::NewTab(Some(PathBuf::from("/tmp")))]);
+}
+
+#[test]
+fn color_answer() {
+    let term = Term::new();
+    let mut buffer = [0u8; 64];
+
+    let size = term.color_answer(VTPaletteSlot::Background, (0xff, 0x80, 0x00), &mut buffer).unwrap();
+    assert_eq!(&buffer[..size], &b"\x1b]11;rgb:ffff/8080/0000\x1b\\"[..]);
+    let size = term.color_answer(VTPaletteSlot::Indexed(42), (0, 0, 1), &mut buffer).unwrap();
+    assert_eq!(&buffer[..size], &b"\x1b]4;42;rgb:0000/0000/0101\x1b\\"[..]);
+}
+
 }
@@ -1,10 +1,14 @@
 use std::{mem, ops};
 use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
 use unicode_width::UnicodeWidthChar;
 
 use ::smallstring::*;
 use ::vt::*;
 use ::scrollback::MemScrollback;
+use ::hyperlink::*;
+use ::image::{Image, ImagePlacement};
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -12,17 +16,31 @@ pub struct Style {
     pub col_fg: VTColor,
     pub col_bg: VTColor,
     pub rendition: VTRendition,
+    pub underline: VTUnderline,
+    /// Underline color, `None` means the foreground color
+    pub col_ul: Option<VTColor>,
+    /// Hyperlink handle, see `Screen::hyperlink()`
+    pub link: Option<LinkId>,
 }
 
 impl Style {
-    pub fn with_fg(col_fg: VTColor) -> Style { Style { col_fg, col_bg: VTColor::DefaultBg, rendition: VTRendition::default() } }
-    pub fn with_bg(col_bg: VTColor) -> Style { Style { col_fg: VTColor::DefaultFg, col_bg, rendition: VTRendition::default() } }
-    pub fn with_rendition(rendition: VTRendition) -> Style { Style { col_fg: VTColor::DefaultFg, col_bg: VTColor::DefaultBg, rendition } }
+    pub fn with_fg(col_fg: VTColor) -> Style { Style { col_fg, ..Style::default() } }
+    pub fn with_bg(col_bg: VTColor) -> Style { Style { col_bg, ..Style::default() } }
+    pub fn with_rendition(rendition: VTRendition) -> Style { Style { rendition, ..Style::default() } }
 
     fn is_default(&self) -> bool {
         self.col_fg == VTColor::DefaultFg
         && self.col_bg == VTColor::DefaultBg
         && self.rendition | VTRendition::DIRTY == VTRendition::default()
+        && self.underline == VTUnderline::None
+        && self.col_ul.is_none()
+        && self.link.is_none()
+    }
+
+    /// The style used for blank cells created by erasing, inserting etc.
+    /// These aren't part of any hyperlink.
+    fn blank(&self) -> Style {
+        Style { link: None, ..*self }
     }
 }
 
@@ -32,6 +50,9 @@ impl Default for Style {
             col_fg: VTColor::DefaultFg,
             col_bg: VTColor::DefaultBg,
             rendition: VTRendition::default(),
+            underline: VTUnderline::default(),
+            col_ul: None,
+            link: None,
         }
     }
 }
@@ -93,7 +114,11 @@ impl Cell {
         res
     }
 
+    /// Append a (combining) character, characters beyond `CELL_BYTES_MAX` bytes are dropped
     pub fn push(&mut self, ch: char) {
+        if self.chars.len() + ch.len_utf8() > CELL_BYTES_MAX {
+            return;
+        }
         self.chars.push(ch);
         self.set_dirty(true);
     }
@@ -105,6 +130,9 @@ impl Cell {
     pub fn col_fg(&self) -> VTColor { self.style.col_fg }
     pub fn col_bg(&self) -> VTColor { self.style.col_bg }
     pub fn rendition(&self) -> VTRendition { self.style.rendition }
+    pub fn underline(&self) -> VTUnderline { self.style.underline }
+    pub fn col_ul(&self) -> Option<VTColor> { self.style.col_ul }
+    pub fn link(&self) -> Option<LinkId> { self.style.link }
 
     fn is_empty(&self) -> bool {
         self.as_str() == " " && self.style.is_default()
@@ -130,9 +158,117 @@ pub const GRAPHICS: [char ; 32] = [
     '\u{252c}', '\u{2502}', '\u{2264}', '\u{2265}', '\u{03c0}', '\u{2260}', '\u{00a3}', '\u{00b7}',   // w through ~
 ];
 
+/// DEC Supplemental Graphic, `!` through `~`
+pub const DEC_SUPPLEMENTAL: [char ; 94] = [
+    '\u{00a1}', '\u{00a2}', '\u{00a3}', '\u{00a4}', '\u{00a5}', '\u{00a6}', '\u{00a7}', '\u{00a4}',   // ! through (
+    '\u{00a9}', '\u{00aa}', '\u{00ab}', '\u{00ac}', '\u{00ad}', '\u{00ae}', '\u{00af}', '\u{00b0}',   // ) through 0
+    '\u{00b1}', '\u{00b2}', '\u{00b3}', '\u{00b4}', '\u{00b5}', '\u{00b6}', '\u{00b7}', '\u{00b8}',   // 1 through 8
+    '\u{00b9}', '\u{00ba}', '\u{00bb}', '\u{00bc}', '\u{00bd}', '\u{00be}', '\u{00bf}', '\u{00c0}',   // 9 through @
+    '\u{00c1}', '\u{00c2}', '\u{00c3}', '\u{00c4}', '\u{00c5}', '\u{00c6}', '\u{00c7}', '\u{00c8}',   // A through H
+    '\u{00c9}', '\u{00ca}', '\u{00cb}', '\u{00cc}', '\u{00cd}', '\u{00ce}', '\u{00cf}', '\u{00d0}',   // I through P
+    '\u{00d1}', '\u{00d2}', '\u{00d3}', '\u{00d4}', '\u{00d5}', '\u{00d6}', '\u{0152}', '\u{00d8}',   // Q through X
+    '\u{00d9}', '\u{00da}', '\u{00db}', '\u{00dc}', '\u{0178}', '\u{00de}', '\u{00df}', '\u{00e0}',   // Y through `
+    '\u{00e1}', '\u{00e2}', '\u{00e3}', '\u{00e4}', '\u{00e5}', '\u{00e6}', '\u{00e7}', '\u{00e8}',   // a through h
+    '\u{00e9}', '\u{00ea}', '\u{00eb}', '\u{00ec}', '\u{00ed}', '\u{00ee}', '\u{00ef}', '\u{00f0}',   // i through p
+    '\u{00f1}', '\u{00f2}', '\u{00f3}', '\u{00f4}', '\u{00f5}', '\u{00f6}', '\u{0153}', '\u{00f8}',   // q through x
+    '\u{00f9}', '\u{00fa}', '\u{00fb}', '\u{00fc}', '\u{00ff}', '\u{00fe}',   // y through ~
+];
+
+/// DEC Technical, `!` through `~`. Characters which are undefined or have no Unicode equivalent
+/// are replaced with U+FFFD.
+pub const DEC_TECHNICAL: [char ; 94] = [
+    '\u{23b7}', '\u{250c}', '\u{2500}', '\u{2320}', '\u{2321}', '\u{2502}', '\u{23a1}', '\u{23a3}',   // ! through (
+    '\u{23a4}', '\u{23a6}', '\u{239b}', '\u{239d}', '\u{239e}', '\u{23a0}', '\u{23a8}', '\u{23ac}',   // ) through 0
+    '\u{23b2}', '\u{23b3}', '\u{fffd}', '\u{fffd}', '\u{fffd}', '\u{fffd}', '\u{fffd}', '\u{fffd}',   // 1 through 8
+    '\u{fffd}', '\u{fffd}', '\u{fffd}', '\u{2264}', '\u{2260}', '\u{2265}', '\u{222b}', '\u{2234}',   // 9 through @
+    '\u{221d}', '\u{221e}', '\u{00f7}', '\u{0394}', '\u{2207}', '\u{03a6}', '\u{0393}', '\u{223c}',   // A through H
+    '\u{2243}', '\u{0398}', '\u{00d7}', '\u{039b}', '\u{21d4}', '\u{21d2}', '\u{2261}', '\u{03a0}',   // I through P
+    '\u{03a8}', '\u{fffd}', '\u{03a3}', '\u{fffd}', '\u{fffd}', '\u{221a}', '\u{03a9}', '\u{039e}',   // Q through X
+    '\u{03a5}', '\u{2282}', '\u{2283}', '\u{2229}', '\u{222a}', '\u{2227}', '\u{2228}', '\u{00ac}',   // Y through `
+    '\u{03b1}', '\u{03b2}', '\u{03c7}', '\u{03b4}', '\u{03b5}', '\u{03c6}', '\u{03b3}', '\u{03b7}',   // a through h
+    '\u{03b9}', '\u{03b8}', '\u{03ba}', '\u{03bb}', '\u{fffd}', '\u{03bd}', '\u{2202}', '\u{03c0}',   // i through p
+    '\u{03c8}', '\u{03c1}', '\u{03c3}', '\u{03c4}', '\u{fffd}', '\u{0192}', '\u{03c9}', '\u{03be}',   // q through x
+    '\u{03c5}', '\u{03b6}', '\u{2190}', '\u{2191}', '\u{2192}', '\u{2193}',   // y through ~
+];
+
+/// Characters replaced by the national replacement character sets, in the order of the `NRCS` tables
+pub const NRCS_CHARS: [u8 ; 12] = *b"#@[\\]^_`{|}~";
+
+/// National replacement character sets, replacements for each of the `NRCS_CHARS`
+pub const NRCS: [(VTNrcs, [char ; 12]) ; 11] = [
+    (VTNrcs::Dutch, ['\u{00a3}', '\u{00be}', '\u{0133}', '\u{00bd}', '|', '^', '_', '`', '\u{00a8}', '\u{0192}', '\u{00bc}', '\u{00b4}']),
+    (VTNrcs::Finnish, ['#', '@', '\u{00c4}', '\u{00d6}', '\u{00c5}', '\u{00dc}', '_', '\u{00e9}', '\u{00e4}', '\u{00f6}', '\u{00e5}', '\u{00fc}']),
+    (VTNrcs::French, ['\u{00a3}', '\u{00e0}', '\u{00b0}', '\u{00e7}', '\u{00a7}', '^', '_', '`', '\u{00e9}', '\u{00f9}', '\u{00e8}', '\u{00a8}']),
+    (VTNrcs::FrenchCanadian, ['#', '\u{00e0}', '\u{00e2}', '\u{00e7}', '\u{00ea}', '\u{00ee}', '_', '\u{00f4}', '\u{00e9}', '\u{00f9}', '\u{00e8}', '\u{00fb}']),
+    (VTNrcs::German, ['#', '\u{00a7}', '\u{00c4}', '\u{00d6}', '\u{00dc}', '^', '_', '`', '\u{00e4}', '\u{00f6}', '\u{00fc}', '\u{00df}']),
+    (VTNrcs::Italian, ['\u{00a3}', '\u{00a7}', '\u{00b0}', '\u{00e7}', '\u{00e9}', '^', '_', '\u{00f9}', '\u{00e0}', '\u{00f2}', '\u{00e8}', '\u{00ec}']),
+    (VTNrcs::NorwegianDanish, ['#', '\u{00c4}', '\u{00c6}', '\u{00d8}', '\u{00c5}', '\u{00dc}', '_', '\u{00e4}', '\u{00e6}', '\u{00f8}', '\u{00e5}', '\u{00fc}']),
+    (VTNrcs::Portuguese, ['#', '@', '\u{00c3}', '\u{00c7}', '\u{00d5}', '^', '_', '`', '\u{00e3}', '\u{00e7}', '\u{00f5}', '~']),
+    (VTNrcs::Spanish, ['\u{00a3}', '\u{00a7}', '\u{00a1}', '\u{00d1}', '\u{00bf}', '^', '_', '`', '\u{00b0}', '\u{00f1}', '\u{00e7}', '~']),
+    (VTNrcs::Swedish, ['#', '\u{00c9}', '\u{00c4}', '\u{00d6}', '\u{00c5}', '\u{00dc}', '_', '\u{00e9}', '\u{00e4}', '\u{00f6}', '\u{00e5}', '\u{00fc}']),
+    (VTNrcs::Swiss, ['\u{00f9}', '\u{00e0}', '\u{00e9}', '\u{00e7}', '\u{00ea}', '\u{00ee}', '\u{00e8}', '\u{00f4}', '\u{00e4}', '\u{00f6}', '\u{00fc}', '\u{00fb}']),
+];
+
+/// Map the character `code` (0x20 through 0x7f) as found in the `charset`, `None` if it stays the same
+fn charset_map(charset: VTCharset, code: u8) -> Option<char> {
+    use VTCharset::*;
+
+    let index = code as usize;
+    match (charset, code) {
+        (Graphics, 0x5f ... 0x7e) => Some(GRAPHICS[index - 0x5f]),
+        (Uk, b'#') => Some('\u{00a3}'),
+        (DecSupplemental, 0x21 ... 0x7e) => Some(DEC_SUPPLEMENTAL[index - 0x21]),
+        (DecTechnical, 0x21 ... 0x7e) => Some(DEC_TECHNICAL[index - 0x21]),
+        (Latin1, _) => Some(char::from(code + 0x80)),
+        (Nrcs(nrcs), _) => {
+            let pos = NRCS_CHARS.iter().position(|&c| c == code)?;
+            NRCS.iter().find(|&&(n, _)| n == nrcs).map(|(_, chars)| chars[pos])
+        },
+        _ => None,
+    }
+}
+
+/// Shell integration mark (OSC 133) recorded on a `Line`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineMark {
+    pub mark: VTSemanticMark,
+    /// Column at which the mark was placed
+    pub x: u32,
+    /// When the mark was placed, used to compute command durations
+    pub time: Instant,
+}
+
+/// Slice of an image placed on a `Line`, ie. one cell row worth of the placement's pixel rows
+#[derive(Debug, Clone)]
+pub struct LineImage {
+    /// The placement this slice belongs to, shared by all of its slices
+    pub placement: Arc<ImagePlacement>,
+    /// Column of the left edge of the placement
+    pub x: u32,
+    /// Number of columns covered by the placement
+    pub cols: u32,
+    /// Which cell row of the placement this slice is, ie. the slice starts at the pixel row `row * cell height`
+    /// of the placement (including its offset)
+    pub row: u32,
+}
+
+impl LineImage {
+    fn overlaps(&self, start: u32, end: u32) -> bool {
+        self.x < end && self.x + self.cols > start
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Line {
     cells: Vec<Cell>,
+    /// Shell integration marks, in the order they were placed
+    marks: Vec<LineMark>,
+    /// Image slices, in the order they were placed (ie. bottom to top)
+    images: Vec<LineImage>,
+    /// Bookmark label, if the line is bookmarked
+    bookmark: Option<Box<str>>,
+    /// Size attribute, double-width and double-height lines only use the left half of the cells
+    size: VTLineSize,
     dirty: bool,
 }
 
@@ -140,6 +276,10 @@ impl Line {
     pub fn new() -> Line {
         Line {
             cells: Vec::new(),
+            marks: Vec::new(),
+            images: Vec::new(),
+            bookmark: None,
+            size: VTLineSize::Single,
             dirty: true,
         }
     }
@@ -147,19 +287,112 @@ impl Line {
     pub fn with_size(ch: Cell, size: u32) -> Line {
         Line {
             cells: vec![ch ; size as usize],
+            marks: Vec::new(),
+            images: Vec::new(),
+            bookmark: None,
+            size: VTLineSize::Single,
             dirty: true,
         }
     }
 
+    pub fn marks(&self) -> &[LineMark] { &self.marks }
+
+    pub fn images(&self) -> &[LineImage] { &self.images }
+
+    pub fn bookmark(&self) -> Option<&str> { self.bookmark.as_deref() }
+
+    pub fn size(&self) -> VTLineSize { self.size }
+
+    /// Place an image slice. Anonymous slices (ie. not placed by the kitty graphics protocol)
+    /// are dropped if the new slice would hide them, so that they don't pile up.
+    fn put_image(&mut self, image: LineImage) {
+        if image.placement.id.is_none() {
+            let (start, end, z) = (image.x, image.x + image.cols, image.placement.z);
+            self.images.retain(|img| img.placement.id.is_some() || img.x < start || img.x + img.cols > end || img.placement.z != z);
+        }
+        self.images.push(image);
+        self.dirty = true;
+    }
+
+    /// Textual content of the cells `start .. end`, without trailing whitespace
+    pub fn text(&self, start: usize, end: usize) -> String {
+        let end = end.min(self.cells.len());
+        let mut res = String::new();
+        let mut wide = false;
+        for cell in self.cells.iter().take(end).skip(start) {
+            // Skip the placeholder cell following a wide character
+            if !wide {
+                res.push_str(cell.as_str());
+            }
+            wide = cell.style.rendition.contains(VTRendition::WIDE);
+        }
+
+        let trimmed = res.trim_end().len();
+        res.truncate(trimmed);
+        res
+    }
+
     pub fn reset_dirty(&mut self) -> bool { mem::replace(&mut self.dirty, false) }
 
     fn fill(&mut self, start: usize, end: usize, value: Cell) {
+        self.drop_images(start, end);
         self.cells.iter_mut()
             .skip(start)
             .take(end.saturating_sub(start))
             .for_each(|c| *c = value.clone());
     }
 
+    /// Insert `num` copies of `value` at `start`, shifting the cells up to `end` right.
+    /// Cells shifted past `end` are discarded.
+    fn insert(&mut self, start: usize, end: usize, num: usize, value: Cell) {
+        let end = end.min(self.cells.len());
+        if start >= end { return; }
+        let num = num.min(end - start);
+
+        self.split_wide(start);
+        self.drop_images(start, end);
+        self.cells[start .. end].rotate_right(num);
+        self.cells[start .. start + num].iter_mut().for_each(|c| *c = value.clone());
+        // A wide character may have lost its second half
+        self.split_wide(end);
+        self.dirty = true;
+    }
+
+    /// Remove `num` cells at `start`, shifting the cells up to `end` left and filling the gap with `value`
+    fn delete(&mut self, start: usize, end: usize, num: usize, value: Cell) {
+        let end = end.min(self.cells.len());
+        if start >= end { return; }
+        let num = num.min(end - start);
+
+        self.split_wide(start);
+        self.split_wide(end);
+        self.drop_images(start, end);
+        self.cells[start .. end].rotate_left(num);
+        self.cells[end - num .. end].iter_mut().for_each(|c| *c = value.clone());
+        self.dirty = true;
+    }
+
+    /// Replace a wide character straddling the boundary before `pos` with a blank,
+    /// so that shifting cells doesn't separate its two halves
+    fn split_wide(&mut self, pos: usize) {
+        if pos == 0 || pos > self.cells.len() { return; }
+        let cell = &mut self.cells[pos - 1];
+        if cell.style.rendition.contains(VTRendition::WIDE) {
+            let mut style = cell.style;
+            style.rendition.remove(VTRendition::WIDE);
+            *cell = Cell::new(' ', style);
+        }
+    }
+
+    /// Erasing or shifting any part of an image slice drops the whole slice
+    fn drop_images(&mut self, start: usize, end: usize) {
+        let images_len = self.images.len();
+        self.images.retain(|img| !img.overlaps(start as u32, end as u32));
+        if self.images.len() != images_len {
+            self.dirty = true;
+        }
+    }
+
     fn is_empty(&self) -> bool {
         let default_cell = Cell::default();
         self.cells.iter().all(|cell| cell.is_empty())
@@ -176,7 +409,7 @@ impl ops::DerefMut for Line {
 }
 
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 pub struct Cursor {
     /// Horizontal position on the screen
     x: u32,
@@ -184,55 +417,94 @@ pub struct Cursor {
     y: u32,
     /// Current format of this cursor
     style: Style,
-    /// Currently used charset
-    charset: VTCharset,
+    /// Charset slot invoked into GL, used for ASCII characters
+    gl: u32,
+    /// Charset slot invoked into GR. Tracked only, as GR applies to 8-bit bytes and the input is UTF-8.
+    gr: u32,
+    /// Charset slot used for the next character only
+    single_shift: Option<u32>,
     /// Charset slots (four by specification)
     charsets: [VTCharset ; 4],
     /// Whether VTMode::ORIGIN is active, only used for cursor save & restore
     mode_origin: bool,
 }
 
+impl Default for Cursor {
+    fn default() -> Cursor {
+        Cursor {
+            x: 0,
+            y: 0,
+            style: Style::default(),
+            gl: 0,
+            gr: 2,
+            single_shift: None,
+            charsets: [VTCharset::default() ; 4],
+            mode_origin: false,
+        }
+    }
+}
+
 impl Cursor {
     // pub fn clamp(&mut self, to_size: (u32, u32)) {
     //     if self.x > to_size.0 { self.x = to_size.0; }
     //     if self.y > to_size.1 { self.y = to_size.1; }
     // }
 
-    pub fn charset_use(&mut self, slot: u32) {
-        if let Some(c) = self.charsets.get(slot as usize) {
-            self.charset = *c;
-        }
-    }
-
     fn charset_designate(&mut self, slot: u32, charset: VTCharset) {
         if let Some(c) = self.charsets.get_mut(slot as usize) {
             *c = charset;
         }
     }
+
+    /// Map a printed character through the charset invoked into GL, or the single shifted one.
+    /// Only ASCII characters are mapped: the input is decoded from UTF-8, so there are no 8-bit GR bytes
+    /// and other code points are to be printed as they are.
+    fn charset_map(&mut self, ch: char) -> char {
+        let slot = self.single_shift.take().unwrap_or(self.gl);
+        match ch as u32 {
+            code @ 0x20 ... 0x7f => charset_map(self.charsets[slot as usize], code as u8).unwrap_or(ch),
+            _ => ch,
+        }
+    }
 }
 
 pub const SCREEN_SIZE_MIN: (u32, u32) = (10, 5);
 pub const SCREEN_SIZE_DEFAULT: (u32, u32) = (80, 40);
+pub const CELL_SIZE_DEFAULT: (u32, u32) = (10, 20);
+/// Maximum size of a cell's content in UTF-8 bytes, which bounds combining marks on a single character
+pub const CELL_BYTES_MAX: usize = 64;
+/// Maximum length of a bookmark label in characters
+pub const BOOKMARK_LABEL_MAX: usize = 256;
 
 #[derive(Debug)]
 pub struct Screen {
     /// Size: width, height, in number of characters
     size: (u32, u32),
+    /// Size of a cell in pixels, used to lay out images
+    cell_size: (u32, u32),
     /// Current cursor data
     cursor: Cursor,
     /// Saved cursor data (for the VT cursor save & restore functionality)
     cursor_saved: Cursor,
+    /// Cursor shape, not part of `Cursor` as it isn't saved and restored
+    cursor_shape: VTCursorShape,
+    /// Last printed graphic character, repeated by `repeat_char()`
+    last_char: Option<char>,
     /// Mode
     mode: VTMode,
     /// Scrolling region span: top, bottom; spans the whole screen by default
     /// Warning: `scroll_rg` is 0-indexed, while `set_scroll_region()` has 1-indexed arguments (with `0` being "the default").
     scroll_rg: (u32, u32),
+    /// Left and right margins, 0-indexed and inclusive like `scroll_rg`; span the whole width by default
+    margins: (u32, u32),
     /// Tab stops
     tabs: Vec<bool>,
     /// The actual character data
     lines: VecDeque<Line>,
     /// Scrollback, if any
     scrollback: Option<MemScrollback>,
+    /// Hyperlinks referenced by cells both on the screen and in the scrollback
+    links: Hyperlinks,
     /// Dirty flag: indicates if the whole screen needs re-rendering
     dirty: bool,
     /// Records number of scrolled lines for the purposes of rendering
@@ -251,34 +523,261 @@ impl Screen {
             lines.push_back(Line::with_size(Cell::default(), size.0));
         }
 
-        let tabs = (0 .. size.0).map(|i| i > 0 && i % 8 == 0).collect();
+        let tabs = Self::default_tabs(size.0);
 
         Screen {
             size,
+            cell_size: CELL_SIZE_DEFAULT,
             cursor: Cursor::default(),
             cursor_saved: Cursor::default(),
+            cursor_shape: VTCursorShape::default(),
+            last_char: None,
             mode: VTMode::default(),
             scroll_rg: (0, size.1 - 1),
+            margins: (0, size.0 - 1),
             tabs,
             lines,
             scrollback: None,
+            links: Hyperlinks::new(),
             dirty: true,
             scrolled_lines: 0,
         }
     }
 
+    /// Tab stops every 8 columns
+    fn default_tabs(cols: u32) -> Vec<bool> {
+        (0 .. cols).map(|i| i > 0 && i % 8 == 0).collect()
+    }
+
     pub fn with_scrollback(mut self, scrollback: MemScrollback) -> Screen {
         self.scrollback = Some(scrollback);
         self
     }
 
+    /// Size in cells: columns, rows
+    pub fn size(&self) -> (u32, u32) { self.size }
+
+    /// The style applied to newly written characters
+    pub fn current_style(&self) -> Style { self.cursor.style }
+
+    pub fn cursor_shape(&self) -> VTCursorShape { self.cursor_shape }
+    pub fn cursor_visible(&self) -> bool { self.mode.contains(VTMode::CURSOR_VISIBLE) }
+    pub fn cursor_blink(&self) -> bool { self.mode.contains(VTMode::CURSOR_BLINK) }
+
+    pub fn set_cursor_shape(&mut self, shape: VTCursorShape) {
+        self.cursor_shape = shape;
+    }
+
+    /// Scrolling region top and bottom lines, 1-indexed
+    pub fn scroll_region(&self) -> (u32, u32) { (self.scroll_rg.0 + 1, self.scroll_rg.1 + 1) }
+
+    /// Left and right margin columns, 1-indexed
+    pub fn margins(&self) -> (u32, u32) { (self.margins.0 + 1, self.margins.1 + 1) }
+
+    pub fn scrollback(&self) -> Option<&MemScrollback> {
+        self.scrollback.as_ref()
+    }
+
+    pub fn clear_scrollback(&mut self) {
+        if let Some(scrollback) = self.scrollback.as_mut() {
+            scrollback.clear();
+            self.dirty = true;
+        }
+        self.collect_links();
+    }
+
+    /// Rebuild the hyperlink table with only the links referenced on the screen. Scrollback lines can't be
+    /// updated, so this only happens while the scrollback is empty.
+    fn collect_links(&mut self) {
+        if self.scrollback.as_ref().map_or(0, |sb| sb.len()) > 0 {
+            return;
+        }
+
+        let mut links = Hyperlinks::new();
+        {
+            let old = &self.links;
+            let styles = self.lines.iter_mut().flat_map(|line| line.iter_mut().map(|cell| &mut cell.style))
+                .chain(Some(&mut self.cursor.style))
+                .chain(Some(&mut self.cursor_saved.style));
+            for style in styles {
+                style.link = style.link.and_then(|id| old.copy_to(id, &mut links));
+            }
+        }
+        self.links = links;
+    }
+
+    /// Soft reset (DECSTR) of the screen's state: the scrolling region, the rendition, character sets
+    /// and the saved cursor are reset. Modes are reset by the `TermState`.
+    pub fn soft_reset(&mut self) {
+        self.scroll_rg = (0, self.size.1 - 1);
+        self.margins = (0, self.size.0 - 1);
+        self.cursor = Cursor { x: self.cursor.x, y: self.cursor.y, ..Cursor::default() };
+        self.cursor_saved = Cursor::default();
+    }
+
+    /// Number of lines in the scrollback (if any) and on the screen.
+    /// History lines are indexed from the oldest scrollback line to the last screen line.
+    pub fn history_len(&self) -> usize {
+        self.scrollback.as_ref().map_or(0, |sb| sb.len()) + self.lines.len()
+    }
+
+    fn history_split(&self, index: usize) -> Result<usize, usize> {
+        let sb_len = self.scrollback.as_ref().map_or(0, |sb| sb.len());
+        if index < sb_len { Ok(index) } else { Err(index - sb_len) }
+    }
+
+    /// Shell integration marks of a history line
+    pub fn history_marks(&self, index: usize) -> &[LineMark] {
+        match self.history_split(index) {
+            Ok(index) => self.scrollback.as_ref().and_then(|sb| sb.get(index)).map_or(&[], |line| line.marks()),
+            Err(index) => self.lines.get(index).map_or(&[], |line| line.marks()),
+        }
+    }
+
+    /// Bookmark label of a history line, if it's bookmarked
+    pub fn history_bookmark(&self, index: usize) -> Option<&str> {
+        match self.history_split(index) {
+            Ok(index) => self.scrollback.as_ref().and_then(|sb| sb.get(index)).and_then(|line| line.bookmark()),
+            Err(index) => self.lines.get(index).and_then(|line| line.bookmark()),
+        }
+    }
+
+    /// Size attribute of a history line
+    pub fn history_line_size(&self, index: usize) -> VTLineSize {
+        match self.history_split(index) {
+            Ok(index) => self.scrollback.as_ref().and_then(|sb| sb.get(index)).map_or(VTLineSize::Single, |line| line.size()),
+            Err(index) => self.lines.get(index).map_or(VTLineSize::Single, |line| line.size()),
+        }
+    }
+
+    /// Bookmarked history lines along with their labels, oldest first
+    pub fn bookmarks(&self) -> Vec<(usize, &str)> {
+        (0 .. self.history_len())
+            .filter_map(|index| self.history_bookmark(index).map(|label| (index, label)))
+            .collect()
+    }
+
+    /// Bookmark the cursor line, replacing its previous bookmark if any
+    pub fn bookmark(&mut self, label: &str) {
+        let label = match label.char_indices().nth(BOOKMARK_LABEL_MAX) {
+            Some((end, _)) => &label[..end],
+            None => label,
+        };
+        self.current_line().bookmark = Some(label.into());
+    }
+
+    /// Textual content of the cells `start .. end` of a history line, without trailing whitespace
+    pub fn history_text(&self, index: usize, start: usize, end: usize) -> String {
+        match self.history_split(index) {
+            Ok(index) => self.scrollback.as_ref().and_then(|sb| sb.get(index)).map_or(String::new(), |line| line.text(start, end)),
+            Err(index) => self.lines.get(index).map_or(String::new(), |line| line.text(start, end)),
+        }
+    }
+
+    /// Look up a hyperlink by its handle, see `Cell::link()`
+    pub fn hyperlink(&self, id: LinkId) -> Option<&VTHyperlink> {
+        self.links.get(id)
+    }
+
+    /// Get the hyperlink under the cell at `x`, `y` (0-indexed), if any
+    pub fn hyperlink_at(&self, x: u32, y: u32) -> Option<&VTHyperlink> {
+        self.lines.get(y as usize)
+            .and_then(|line| line.get(x as usize))
+            .and_then(|cell| cell.link())
+            .and_then(|id| self.links.get(id))
+    }
+
+    pub fn cell_size(&self) -> (u32, u32) { self.cell_size }
+
+    /// Set the size of a cell in pixels, this only affects images placed afterwards
+    pub fn set_cell_size(&mut self, width: u32, height: u32) {
+        self.cell_size = (width.max(1), height.max(1));
+    }
+
+    /// Lay out `placement` from the cursor down, one slice per line. Returns the number of columns it covers.
+    /// If `scroll` is set, the screen is scrolled as needed and the cursor ends up on the last line of the placement,
+    /// otherwise the placement is clipped at the bottom of the screen and the cursor doesn't move.
+    fn place(&mut self, placement: ImagePlacement, scroll: bool) -> u32 {
+        let width = placement.offset.0 + placement.size.0;
+        let height = placement.offset.1 + placement.size.1;
+        let cols = width.div_ceil(self.cell_size.0).max(1);
+        let rows = height.div_ceil(self.cell_size.1).max(1);
+        let (x, y) = (self.cursor.x, self.y());
+        let placement = Arc::new(placement);
+
+        for row in 0 .. rows {
+            let slice = LineImage { placement: placement.clone(), x, cols, row };
+            if scroll {
+                if row > 0 {
+                    self.index(true);
+                }
+                self.current_line().put_image(slice);
+            } else if let Some(line) = self.lines.get_mut(y + row as usize) {
+                line.put_image(slice);
+            }
+        }
+
+        cols
+    }
+
+    /// Place an image with its top left corner at the cursor.
+    /// The cursor is moved to the line below the image, scrolling as needed.
+    pub fn put_image(&mut self, image: Arc<Image>) {
+        self.place(ImagePlacement::new(image), true);
+        self.index(true);
+    }
+
+    /// Place an image with its top left corner at the cursor. If `move_cursor` is set, the cursor is moved
+    /// past the right edge of the image on its last line, scrolling as needed. Otherwise the image is clipped
+    /// at the bottom of the screen.
+    pub fn put_placement(&mut self, placement: ImagePlacement, move_cursor: bool) {
+        let x = self.cursor.x;
+        let cols = self.place(placement, move_cursor);
+        if move_cursor {
+            self.cursor.x = self.clamp_x(x + cols);
+        }
+    }
+
+    /// Remove placements for which `pred` returns true for any of their slices, along with all of their slices.
+    /// `pred` receives the slice and the line number. Returns the removed placements.
+    pub fn remove_images<F>(&mut self, mut pred: F) -> Vec<Arc<ImagePlacement>> where F: FnMut(&LineImage, usize) -> bool {
+        let mut removed: Vec<Arc<ImagePlacement>> = vec![];
+        for (y, line) in self.lines.iter().enumerate() {
+            for img in line.images.iter() {
+                if !removed.iter().any(|p| Arc::ptr_eq(p, &img.placement)) && pred(img, y) {
+                    removed.push(img.placement.clone());
+                }
+            }
+        }
+
+        if !removed.is_empty() {
+            for line in self.lines.iter_mut() {
+                let images_len = line.images.len();
+                line.images.retain(|img| !removed.iter().any(|p| Arc::ptr_eq(p, &img.placement)));
+                if line.images.len() != images_len {
+                    line.dirty = true;
+                }
+            }
+        }
+
+        removed
+    }
+
+    /// Iterate image slices on the screen along with their line numbers.
+    /// Renderers should draw these on top of the cells of the respective lines.
+    pub fn images(&self) -> impl Iterator<Item=(usize, &LineImage)> {
+        self.lines.iter()
+            .enumerate()
+            .flat_map(|(y, line)| line.images.iter().map(move |img| (y, img)))
+    }
+
     /// Iterate Lines
     pub fn line_iter(&mut self) -> impl ExactSizeIterator + Iterator<Item=&mut Line> {   // XXX: remove?
         self.lines.iter_mut()
     }
 
     fn empty_char(&self) -> Cell {
-        Cell::with_style(self.cursor.style)    // XXX: replace occurences
+        Cell::with_style(self.cursor.style.blank())    // XXX: replace occurences
     }
 
     fn empty_line(&self) -> Line {
@@ -286,24 +785,65 @@ impl Screen {
     }
 
     fn x(&self) -> usize { self.cursor.x as usize }
+
     fn y(&self) -> usize { self.cursor.y as usize }
 
+    /// Columns affected by inserting and deleting characters: the cursor column, which is clamped
+    /// in case a wrap is pending, up to the right margin. Empty if the cursor is outside the margins.
+    fn edit_span(&self) -> (usize, usize) {
+        if !self.cursor_in_mg() {
+            return (self.x(), self.x());
+        }
+        let end = self.cursor_span().1 as usize + 1;
+        (self.x().min(end - 1), end)
+    }
+
     fn clamp_x(&self, x: u32) -> u32 { if x >= self.size.0 { self.size.0 - 1 } else { x } }
     fn clamp_y(&self, y: u32) -> u32 { if y >= self.size.1 { self.size.1 - 1 } else { y } }
 
+    /// Number of usable columns on the line `y`, double-width and double-height lines only have half of them
+    fn line_width(&self, y: u32) -> u32 {
+        match self.lines[y as usize].size {
+            VTLineSize::Single => self.size.0,
+            _ => self.size.0 / 2,
+        }
+    }
+
     fn sr_set(&self) -> bool { self.scroll_rg != (0, self.size.1 - 1) }
+    fn mg_set(&self) -> bool { self.margins != (0, self.size.0 - 1) }
 
     fn cursor_in_sr(&self) -> bool {
         self.cursor.y >= self.scroll_rg.0 && self.cursor.y <= self.scroll_rg.1
     }
 
+    /// Whether the cursor is between the margins, this includes the column past the right margin
+    /// where the cursor is left when a wrap is pending
+    fn cursor_in_mg(&self) -> bool {
+        self.cursor.x >= self.margins.0 && self.cursor.x <= self.margins.1 + 1
+    }
+
+    /// Columns the cursor is confined to when writing and moving: the margins if it's between them,
+    /// otherwise the whole line. Margins don't apply to double-width lines.
+    fn cursor_span(&self) -> (u32, u32) {
+        let width = self.line_width(self.cursor.y);
+        if width < self.size.0 {
+            (0, width - 1)
+        } else if self.cursor_in_mg() {
+            self.margins
+        } else {
+            (0, self.size.0 - 1)
+        }
+    }
+
     fn cursor_set_pos(&mut self, x: u32, y: u32) {
-        self.cursor.x = self.clamp_x(x);
-        self.cursor.y = if self.mode.contains(VTMode::ORIGIN) {
-            y.min(self.scroll_rg.1).max(self.scroll_rg.0)
+        if self.mode.contains(VTMode::ORIGIN) {
+            self.cursor.x = x.min(self.margins.1).max(self.margins.0);
+            self.cursor.y = y.min(self.scroll_rg.1).max(self.scroll_rg.0);
         } else {
-            self.clamp_y(y)
-        };
+            self.cursor.x = self.clamp_x(x);
+            self.cursor.y = self.clamp_y(y);
+        }
+        self.cursor.x = self.cursor.x.min(self.line_width(self.cursor.y) - 1);
     }
 
     fn current_char(&mut self) -> &mut Cell {
@@ -315,7 +855,8 @@ impl Screen {
         self.lines.get_mut(self.cursor.y as usize).expect("Cursor position out of bounds")
     }
 
-    // pub fn mode(&self) -> VTMode { self.mode }   // XXX: needed? Should not be needed.
+    /// Modes, mirrored from the `TermState`
+    pub fn mode(&self) -> VTMode { self.mode }
 
     fn push_scrollback(&mut self, line: Line) {
         if let Some(ref mut scrollback) = self.scrollback.as_mut() {
@@ -325,6 +866,10 @@ impl Screen {
 
     /// Scroll lines in the range (top, bottom), inserting blank lines and popping to scrollback if appropriate
     fn scroll_generic(&mut self, range: (u32, u32), num: i32) {
+        if self.mg_set() {
+            return self.scroll_margins(range, num);
+        }
+
         // FIXME: dirty marking
 
         let scroll_up = num >= 0;
@@ -366,6 +911,39 @@ impl Screen {
         }
     }
 
+    /// Scroll the cells between the margins of the lines in the range (top, bottom).
+    /// Nothing goes to the scrollback as the lines are only partially scrolled.
+    fn scroll_margins(&mut self, range: (u32, u32), num: i32) {
+        let (top, bottom) = (range.0 as i32, range.1 as i32);
+        let (left, right) = (self.margins.0 as usize, self.margins.1 as usize + 1);
+        let empty_char = self.empty_char();
+
+        // Wide characters and images crossing the margins can't be kept
+        for y in top .. bottom + 1 {
+            let line = &mut self.lines[y as usize];
+            line.split_wide(left);
+            line.split_wide(right);
+            line.drop_images(left, right);
+            line.dirty = true;
+        }
+
+        // Go in the direction of the scroll so that every line is copied before it's overwritten
+        let mut rows: Vec<i32> = (top .. bottom + 1).collect();
+        if num < 0 {
+            rows.reverse();
+        }
+
+        for y in rows {
+            let src = y + num;
+            let cells = if src >= top && src <= bottom {
+                self.lines[src as usize][left .. right].to_vec()
+            } else {
+                vec![empty_char.clone() ; right - left]
+            };
+            self.lines[y as usize][left .. right].clone_from_slice(&cells);
+        }
+    }
+
     pub fn resize(&mut self, cols: u16, rows: u16) {
         let cols = (cols as u32).max(SCREEN_SIZE_MIN.0);
         let rows = (rows as u32).max(SCREEN_SIZE_MIN.1);
@@ -373,7 +951,7 @@ impl Screen {
         // Resize each line
         if cols > self.size.0 {
             for line in self.lines.iter_mut() {
-                let ch = Cell::with_style(line.last().unwrap().style);
+                let ch = Cell::with_style(line.last().unwrap().style.blank());
                 line.resize(cols as usize, ch);
             }
         } else if cols < self.size.0 {
@@ -405,7 +983,7 @@ impl Screen {
             self.scroll_rg.0 = self.scroll_rg.0.min(self.scroll_rg.1 - 1);
         } else if rows > self.size.1 {
             for _ in self.size.1 .. rows {
-                self.lines.push_back(Line::with_size(Cell::with_style(self.cursor.style), cols));
+                self.lines.push_back(Line::with_size(Cell::with_style(self.cursor.style.blank()), cols));
             }
 
             // If scrolling region bottom line is the last line, expand it
@@ -414,6 +992,11 @@ impl Screen {
             }
         }
 
+        // Margins don't survive a change of width
+        if cols != self.size.0 {
+            self.margins = (0, cols - 1);
+        }
+
         // Resize tabs
         if cols > self.size.0 {
             (self.size.0 .. cols)
@@ -437,15 +1020,18 @@ impl Screen {
         mem::replace(&mut self.scrolled_lines, 0)
     }
 
-    pub fn render<F>(&mut self, cell_render: F) where F: Fn(&Cell, usize, usize, bool) {
+    /// Calls `cell_render` with each cell, its position, the size of its line and whether it needs re-rendering.
+    /// Glyphs on lines other than `VTLineSize::Single` are to be scaled, only the left half of their cells is visible.
+    pub fn render<F>(&mut self, cell_render: F) where F: Fn(&Cell, usize, usize, VTLineSize, bool) {
         let screen_dirty = self.reset_dirty();
 
         for (y, line) in self.lines.iter_mut().enumerate() {
             let line_dirty = line.reset_dirty();
+            let size = line.size;
 
             for (x, cell) in line.iter_mut().enumerate() {
                 let cell_dirty = cell.reset_dirty();
-                cell_render(&cell, x, y, screen_dirty || line_dirty || cell_dirty);
+                cell_render(cell, x, y, size, screen_dirty || line_dirty || cell_dirty);
             }
         }
     }
@@ -459,19 +1045,23 @@ impl Default for Screen {
 
 impl VTScreen for Screen {
     fn put_char(&mut self, ch: char) {
-        let ch = match (self.cursor.charset, ch as usize) {
-            (VTCharset::Graphics, ord @ 0x5f ... 0x7e) => GRAPHICS[ord - 0x5f],
-            _ => ch,
-        };
-
         let width = ch.width().expect("Unexpected control character");
 
         if width == 0 {
             self.current_char().push(ch);
         } else {
+            // REP maps the character through the charsets anew
+            self.last_char = Some(ch);
+            let ch = self.cursor.charset_map(ch);
+
+            let (left, right) = self.cursor_span();
+            if self.cursor.x > right + 1 {
+                // The cursor is past the end of a double-width line, rather than pending a wrap
+                self.cursor.x = right;
+            }
             let x_last_valid = match width {
-                1 => self.size.0 - 1,
-                2 => self.size.0 - 2,
+                1 => right,
+                2 => right - 1,
                 _ => panic!("Unexpected character width: {}", width),
             };
 
@@ -479,15 +1069,20 @@ impl VTScreen for Screen {
             if self.cursor.x > x_last_valid {
                 if self.mode.contains(VTMode::WRAP) && self.cursor_in_sr() {
                     self.newline();
-                    self.cursor.x = 0;
+                    self.cursor.x = left;
                 } else {
                     self.cursor.x = x_last_valid;
                 }
             }
 
             let (x, y) = (self.x(), self.y());
+            let end = right as usize + 1;
             let line = &mut self.lines[y];
 
+            if self.mode.contains(VTMode::INSERT) {
+                line.insert(x, end, width, Cell::with_style(self.cursor.style.blank()));
+            }
+
             if x > 0 {
                 let prev = &mut line[x];
                 if prev.style.rendition.contains(VTRendition::WIDE) {
@@ -497,12 +1092,6 @@ impl VTScreen for Screen {
                 }
             }
 
-            if self.mode.contains(VTMode::INSERT) {
-                for c in line[x + 1 ..].iter_mut() {
-                    *c = Cell::with_style(self.cursor.style);
-                }
-            }
-
             let mut ch = Cell::new(ch, self.cursor.style);
             if width == 2 {
                 ch.style.rendition.insert(VTRendition::WIDE);
@@ -514,23 +1103,40 @@ impl VTScreen for Screen {
         }
     }
 
-    fn put_chars(&mut self, num: u32) {
-        let x = self.x();
+    fn insert_chars(&mut self, num: u32) {
+        let (x, end) = self.edit_span();
         let empty_char = self.empty_char();
-        let line = self.current_line();
-        let end = line.len().min(x + num as usize);
-        for c in line[x..end].iter_mut() {
-            *c = empty_char.clone();
+        self.cursor.x = x as u32;
+        self.current_line().insert(x, end, num as usize, empty_char);
+    }
+
+    fn delete_chars(&mut self, num: u32) {
+        let (x, end) = self.edit_span();
+        let empty_char = self.empty_char();
+        self.cursor.x = x as u32;
+        self.current_line().delete(x, end, num as usize, empty_char);
+    }
+
+    fn repeat_char(&mut self, num: u32) {
+        if let Some(ch) = self.last_char {
+            // No point in repeating more than what fits the screen
+            for _ in 0 .. num.min(self.size.0 * self.size.1) {
+                self.put_char(ch);
+            }
         }
     }
 
     fn newline(&mut self) {
         self.index(true);
         if self.mode.contains(VTMode::NEWLINE) {
-            self.cursor.x = 0;
+            self.carriage_return();
         }
     }
 
+    fn carriage_return(&mut self) {
+        self.cursor.x = if self.cursor.x >= self.margins.0 { self.margins.0 } else { 0 };
+    }
+
     fn index(&mut self, forward: bool) {
         // If the cursor is in the middle of the screen, just move it in the appropriate direction.
         // If it is at the edge of the scrolling region, peform a scroll up/down instead.
@@ -547,7 +1153,7 @@ impl VTScreen for Screen {
 
     fn next_line(&mut self) {
         self.index(true);
-        self.cursor.x = 0;
+        self.carriage_return();
     }
 
     fn erase(&mut self, erase: VTErase) {
@@ -584,7 +1190,7 @@ impl VTScreen for Screen {
             Line => { self.current_line().fill(0, w, empty_char); },
             LineLeft => { self.current_line().fill(0, x + 1, empty_char); },
             LineRight => { self.current_line().fill(x, w, empty_char); },
-            NumChars(num) => { self.current_line().fill(x, num as usize, empty_char); },
+            NumChars(num) => { self.current_line().fill(x, x + num as usize, empty_char); },
         }
     }
 
@@ -618,7 +1224,7 @@ impl VTScreen for Screen {
         if num == 0 { num = 1; }
         let num = num.min(srsize).max(-srsize);
 
-        if !self.sr_set() {
+        if !self.sr_set() && !self.mg_set() {
             // Scroll the whole screen; this is the common case
 
             for _ in 0 .. num {
@@ -650,14 +1256,15 @@ impl VTScreen for Screen {
     }
 
     fn scroll_at_cursor(&mut self, mut num: i32) {
-        if !self.cursor_in_sr() { return; }
+        if !self.cursor_in_sr() || !self.cursor_in_mg() { return; }
 
-        let x = self.cursor.x;
+        let y = self.cursor.y;
         let scroll_rg_bottom = self.scroll_rg.1;
-        let num_lines = (scroll_rg_bottom - x + 1) as i32;
+        let num_lines = (scroll_rg_bottom - y + 1) as i32;
         if num == 0 { num = 1; }
         num = num.min(num_lines).max(-num_lines);
-        self.scroll_generic((x, scroll_rg_bottom), num);
+        self.scroll_generic((y, scroll_rg_bottom), num);
+        self.cursor.x = self.margins.0;
     }
 
     fn set_scroll_region(&mut self, mut top: u32, mut bottom: u32) {
@@ -674,40 +1281,88 @@ impl VTScreen for Screen {
 
         // 3. Apply and reset cursor
         self.scroll_rg = (top, bottom);
-        self.cursor.x = 0;
-        self.cursor.y = 0;
+        self.cursor_set(Some(1), Some(1));
+    }
+
+    fn set_margins(&mut self, mut left: u32, mut right: u32) {
+        if !self.mode.contains(VTMode::LR_MARGINS) { return; }
+
+        left = left.saturating_sub(1);
+        if right == 0 { right = self.size.0 - 1 }
+        else { right -= 1; }
+
+        if right <= left || right >= self.size.0 {
+            left = 0;
+            right = self.size.0 - 1;
+        }
+
+        self.margins = (left, right);
+        self.cursor_set(Some(1), Some(1));
     }
 
     fn set_mode(&mut self, mode: VTMode, enable: bool) {
         self.mode.set(mode, enable);
+        if mode.contains(VTMode::LR_MARGINS) && !enable {
+            self.margins = (0, self.size.0 - 1);
+        }
     }
 
     fn set_rendition(&mut self, rend: VTRendition, enable: bool) {
         self.cursor.style.rendition.set(rend, enable);
     }
 
+    fn set_underline(&mut self, underline: VTUnderline) { self.cursor.style.underline = underline; }
+    fn set_underline_color(&mut self, color: Option<VTColor>) { self.cursor.style.col_ul = color; }
     fn set_fg(&mut self, color: VTColor) { self.cursor.style.col_fg = color; }
     fn set_bg(&mut self, color: VTColor) { self.cursor.style.col_bg = color; }
 
-    fn charset_use(&mut self, slot: u32) { self.cursor.charset_use(slot); }
+    fn set_hyperlink(&mut self, link: Option<VTHyperlink>) {
+        self.cursor.style.link = link.and_then(|link| self.links.intern(link));
+    }
+
+    fn semantic_mark(&mut self, mark: VTSemanticMark) {
+        let x = self.cursor.x;
+        self.current_line().marks.push(LineMark { mark, x, time: Instant::now() });
+    }
+
+    fn charset_use(&mut self, slot: u32) { if slot < 4 { self.cursor.gl = slot; } }
+    fn charset_use_gr(&mut self, slot: u32) { if slot < 4 { self.cursor.gr = slot; } }
+    fn charset_single_shift(&mut self, slot: u32) { if slot < 4 { self.cursor.single_shift = Some(slot); } }
     fn charset_designate(&mut self, slot: u32, charset: VTCharset) { self.cursor.charset_designate(slot, charset); }
 
-    fn reset(&mut self) { unimplemented!() }
+    fn reset(&mut self) {
+        let (cols, rows) = self.size;
+
+        self.cursor = Cursor::default();
+        self.cursor_saved = Cursor::default();
+        self.cursor_shape = VTCursorShape::default();
+        self.last_char = None;
+        self.mode = VTMode::default();
+        self.scroll_rg = (0, rows - 1);
+        self.margins = (0, cols - 1);
+        self.tabs = Self::default_tabs(cols);
+        self.lines = (0 .. rows).map(|_| Line::with_size(Cell::default(), cols)).collect();
+        self.collect_links();
+        self.dirty = true;
+    }
 
     fn cursor(&self) -> (u32, u32) { (self.cursor.x + 1, self.cursor.y + 1) }
 
     fn cursor_set(&mut self, x: Option<u32>, y: Option<u32>) {
+        let (ox, oy) = if self.mode.contains(VTMode::ORIGIN) { (self.margins.0, self.scroll_rg.0) } else { (0, 0) };
+        let (cx, cy) = (self.cursor.x, self.cursor.y);
         if let Some(y) = y {
-            let cx = self.cursor.x;
-            self.cursor_set_pos(x.map_or(cx, |x| x - 1), y - 1);
+            self.cursor_set_pos(x.map_or(cx, |x| x - 1 + ox), y - 1 + oy);
         } else if let Some(x) = x {
-            self.cursor.x = self.clamp_x(x - 1);
+            self.cursor_set_pos(x - 1 + ox, cy);
         }
     }
 
     fn cursor_move(&mut self, x: i32, y: i32) {
-        let cx = self.cursor.x as i32 + x;
-        let cy = self.cursor.y as i32 + y;
+        // Horizontal movement stops at the margins if the cursor is between them
+        let (left, right) = self.cursor_span();
+        let cx = (self.cursor.x as i32 + x).min(right as i32).max(left as i32);
+        let cy = (self.cursor.y as i32 + y).max(0);
         self.cursor_set_pos(cx as u32, cy as u32);
     }
 
@@ -723,6 +1378,24 @@ impl VTScreen for Screen {
         self.cursor.y = self.clamp_y(self.cursor.y);
     }
 
+    fn set_line_size(&mut self, size: VTLineSize) {
+        let half = (self.size.0 / 2) as usize;
+        let empty_char = self.empty_char();
+        let line = self.current_line();
+
+        // The right half of a single-width line is lost
+        if line.size == VTLineSize::Single && size != VTLineSize::Single {
+            let end = line.len();
+            line.split_wide(half);
+            line.fill(half, end, empty_char);
+        }
+        line.size = size;
+        line.dirty = true;
+
+        let width = self.line_width(self.cursor.y);
+        self.cursor.x = self.cursor.x.min(width - 1);
+    }
+
     fn alignment_test(&mut self) {
         let eeeeee = Line::with_size(Cell::new('E', self.cursor.style), self.size.0);
         for line in self.lines.iter_mut() {
@@ -738,6 +1411,31 @@ impl VTScreen for Screen {
 mod tests {
 use super::*;
 
+#[test]
+fn screen_links() {
+    let link = |uri: &str| Some(VTHyperlink { id: None, uri: uri.into() });
+
+    let mut screen = Screen::with_size((10, 5)).with_scrollback(MemScrollback::new(1024 * 1024));
+    for uri in &["a", "b", "c"] {
+        screen.set_hyperlink(link(uri));
+        screen.put_char('x');
+    }
+    screen.set_hyperlink(None);
+    assert_eq!(screen.links.len(), 3);
+
+    // Links that scrolled into the scrollback are kept until it's cleared
+    screen.cursor_set(Some(2), Some(5));
+    screen.newline();
+    screen.put_char('y');
+    screen.set_hyperlink(link("c"));
+    screen.clear_scrollback();
+    assert_eq!(screen.links.len(), 1);
+    assert_eq!(screen.current_style().link.and_then(|id| screen.hyperlink(id)), link("c").as_ref());
+
+    screen.reset();
+    assert_eq!(screen.links.len(), 0);
+}
+
 #[test]
 fn screen_scroll() {
     let mut screen = Screen::with_size((1, 10));
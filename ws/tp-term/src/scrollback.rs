@@ -7,53 +7,119 @@ use std::rc::Rc;
 use std::cell::UnsafeCell;
 use std::iter;
 use std::collections::{VecDeque, vec_deque};
+use unicode_width::UnicodeWidthChar;
 
-use ::{Line, Cell, Style, VTColor};
+use ::{Line, LineMark, Cell, Style, VTColor, VTRendition, VTUnderline, VTLineSize, LinkId};
 
 
 bitflags! {
     /// Based on VTRendition, except without stuff that we don't need for scrollback
     /// and additionally with flags for color storage.
-    struct SBRendition: u8 {
-        const NONE       = 0;
-        const BOLD       = 1 << 0;
-        const UNDERLINED = 1 << 1;
-        const INVERSE    = 1 << 2;
-        const BLINKING   = 1 << 3;
-        const INVISIBLE  = 1 << 4;
+    struct SBRendition: u32 {
+        const NONE        = 0;
+        const BOLD        = 1 << 0;
+        const DIM         = 1 << 1;
+        const INVERSE     = 1 << 2;
+        const BLINKING    = 1 << 3;
+        const INVISIBLE   = 1 << 4;
 
         // MemScrollback bookkeeping:
-        const HAS_FG     = 1 << 5;
-        const HAS_BG     = 1 << 6;
-        const LAST       = 1 << 7;
+        const HAS_FG      = 1 << 5;
+        const HAS_BG      = 1 << 6;
+        const LAST        = 1 << 7;
+        const HAS_LINK    = 1 << 8;
+
+        /// Underline style, see `SBRendition::underline()`
+        const UNDERLINE   = 0b111 << 9;
+
+        const ITALIC      = 1 << 12;
+        const STRIKE      = 1 << 13;
+        const OVERLINE    = 1 << 14;
+        const RAPID_BLINK = 1 << 15;
+
+        const HAS_UL      = 1 << 16;
+
+        const WIDE        = 1 << 17;
     }
 }
 
+/// Renditions as stored in the scrollback, the rest of `VTRendition` isn't kept
+const RENDITIONS: [(VTRendition, SBRendition) ; 10] = [
+    (VTRendition::BOLD, SBRendition::BOLD),
+    (VTRendition::DIM, SBRendition::DIM),
+    (VTRendition::INVERSE, SBRendition::INVERSE),
+    (VTRendition::BLINKING, SBRendition::BLINKING),
+    (VTRendition::INVISIBLE, SBRendition::INVISIBLE),
+    (VTRendition::ITALIC, SBRendition::ITALIC),
+    (VTRendition::STRIKE, SBRendition::STRIKE),
+    (VTRendition::OVERLINE, SBRendition::OVERLINE),
+    (VTRendition::RAPID_BLINK, SBRendition::RAPID_BLINK),
+    (VTRendition::WIDE, SBRendition::WIDE),
+];
+
 impl Default for SBRendition {
     fn default() -> SBRendition { SBRendition::NONE }
 }
 
 impl From<Style> for SBRendition {
     fn from(style: Style) -> SBRendition {
-        let mut res = SBRendition::from_bits_truncate(style.rendition.bits() & 0x1f);
+        let mut res = SBRendition::NONE;
+        for &(vt, sb) in RENDITIONS.iter() {
+            if style.rendition.contains(vt) { res |= sb; }
+        }
+        res |= SBRendition::from_bits_truncate((style.underline as u32) << 9);
         if style.col_fg != VTColor::DefaultFg { res |= SBRendition::HAS_FG; }
         if style.col_bg != VTColor::DefaultBg { res |= SBRendition::HAS_BG; }
+        if style.link.is_some() { res |= SBRendition::HAS_LINK; }
+        if style.col_ul.is_some() { res |= SBRendition::HAS_UL; }
         res
     }
 }
 
 impl SBRendition {
+    /// Size of the encoded flags
+    const SIZE: usize = 3;
+
     fn is_last(&self) -> bool {
         self.contains(SBRendition::LAST)
     }
 
     fn has_fg(&self) -> bool { self.contains(SBRendition::HAS_FG) }
     fn has_bg(&self) -> bool { self.contains(SBRendition::HAS_BG) }
+    fn has_link(&self) -> bool { self.contains(SBRendition::HAS_LINK) }
+    fn has_ul(&self) -> bool { self.contains(SBRendition::HAS_UL) }
+
+    fn rendition(&self) -> VTRendition {
+        RENDITIONS.iter()
+            .filter(|&&(_, sb)| self.contains(sb))
+            .fold(VTRendition::empty(), |res, &(vt, _)| res | vt)
+    }
+
+    fn underline(&self) -> VTUnderline {
+        match (*self & SBRendition::UNDERLINE).bits() >> 9 {
+            1 => VTUnderline::Single,
+            2 => VTUnderline::Double,
+            3 => VTUnderline::Curly,
+            4 => VTUnderline::Dotted,
+            5 => VTUnderline::Dashed,
+            _ => VTUnderline::None,
+        }
+    }
 
     fn header_size(&self) -> usize {
-        2  // flags & size
+        SBRendition::SIZE + 1  // flags & size
         + if self.has_fg() { 4 } else { 0 }
         + if self.has_bg() { 4 } else { 0 }
+        + if self.has_link() { 4 } else { 0 }
+        + if self.has_ul() { 4 } else { 0 }
+    }
+
+    fn memsb_encode(&self) -> [u8 ; SBRendition::SIZE] {
+        [self.bits() as u8, (self.bits() >> 8) as u8, (self.bits() >> 16) as u8]
+    }
+
+    fn memsb_decode(data: &[u8]) -> SBRendition {
+        SBRendition::from_bits_truncate(data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16)
     }
 }
 
@@ -69,6 +135,18 @@ impl VTColor {
     }
 }
 
+impl LinkId {
+    fn memsb_encode(&self) -> [u8 ; 4] {
+        self.as_u32().to_ne_bytes()
+    }
+
+    fn memsb_decode(data: &[u8]) -> Option<LinkId> {
+        let mut id = [0u8 ; 4];
+        id.copy_from_slice(&data[..4]);
+        LinkId::from_u32(u32::from_ne_bytes(id))
+    }
+}
+
 
 const CHUNK_SIZE: usize = 32 * 1024;
 const CHUNK_OVERHEAD: usize = 2 * mem::size_of::<usize>() /* = Rc overhead */ + mem::size_of::<MemSBLine>();
@@ -78,6 +156,12 @@ const CHUNK_OVERHEAD: usize = 2 * mem::size_of::<usize>() /* = Rc overhead */ +
 pub struct MemSBLine {
     chunk: Rc<UnsafeCell<Vec<u8>>>,
     offset: usize,
+    /// Shell integration marks carried over from the screen line, empty for most lines
+    marks: Box<[LineMark]>,
+    /// Bookmark label carried over from the screen line
+    bookmark: Option<Box<str>>,
+    /// Size attribute carried over from the screen line
+    size: VTLineSize,
 }
 
 impl MemSBLine {
@@ -85,6 +169,9 @@ impl MemSBLine {
         let mut res = MemSBLine {
             chunk: Rc::new(UnsafeCell::new(Vec::with_capacity(CHUNK_SIZE))),
             offset: 0,
+            marks: line.marks().into(),
+            bookmark: line.bookmark().map(|label| label.into()),
+            size: line.size(),
         };
         res.encode_line(line);
         res
@@ -96,6 +183,9 @@ impl MemSBLine {
             let mut res = MemSBLine {
                 chunk: Rc::clone(&prev.chunk),
                 offset: prev.chunk().len(),
+                marks: line.marks().into(),
+                bookmark: line.bookmark().map(|label| label.into()),
+                size: line.size(),
             };
             res.encode_line(line);
             Some(res)
@@ -111,16 +201,22 @@ impl MemSBLine {
         let rend: SBRendition = style.into();
 
         // Encode flags and size
-        self.chunk_mut().push(rend.bits());
+        self.chunk_mut().extend(&rend.memsb_encode());
         self.chunk_mut().push(0);   // Placeholder, will be modified as needed
 
-        // Encode colors if needed
+        // Encode colors and link if needed
         if rend.has_fg() {
             self.chunk_mut().extend(&style.col_fg.memsb_encode());
         }
         if rend.has_bg() {
             self.chunk_mut().extend(&style.col_bg.memsb_encode());
         }
+        if let Some(link) = style.link {
+            self.chunk_mut().extend(&link.memsb_encode());
+        }
+        if let Some(col_ul) = style.col_ul {
+            self.chunk_mut().extend(&col_ul.memsb_encode());
+        }
     }
 
     fn trim_count(line: &Line) -> usize {
@@ -139,23 +235,24 @@ impl MemSBLine {
         self.encode_piece_header(style);
 
         for cell in &line[..num_cells] {
-            if cell.style != style || piece_size == 255 {
+            let cell_size = cell.as_str().len() as u32;
+            if cell.style != style || piece_size + cell_size > 255 {
                 // Need to finalize the current piece and start a new one
-                self.chunk_mut()[piece_start + 1] = piece_size as u8;    // Write the final size of the current piece
+                self.chunk_mut()[piece_start + SBRendition::SIZE] = piece_size as u8;    // Write the final size of the current piece
                 style = cell.style;
-                piece_size = 1;
+                piece_size = cell_size;
                 piece_start = self.chunk().len();
                 self.encode_piece_header(style);
             } else {
-                piece_size += 1;
+                piece_size += cell_size;
             }
 
             self.chunk_mut().extend(cell.as_str().as_bytes());
         }
 
         // Finalize the last piece
-        self.chunk_mut()[piece_start] |= SBRendition::LAST.bits();
-        self.chunk_mut()[piece_start + 1] = piece_size as u8;
+        self.chunk_mut()[piece_start] |= SBRendition::LAST.bits() as u8;
+        self.chunk_mut()[piece_start + SBRendition::SIZE] = piece_size as u8;
     }
 
     fn line_size(line: &Line) -> usize {
@@ -170,12 +267,13 @@ impl MemSBLine {
         let mut piece_size = 0u32;
 
         line[..num_cells].iter().fold((rend.header_size(), style), |(mut size, style), cell| {
-            if cell.style != style || piece_size == 255 {
+            let cell_size = cell.as_str().len() as u32;
+            if cell.style != style || piece_size + cell_size > 255 {
                 let rend: SBRendition = cell.style.into();
                 size += rend.header_size();
-                piece_size = 1;
+                piece_size = cell_size;
             } else {
-                piece_size += 1;
+                piece_size += cell_size;
             }
 
             (size + cell.as_str().len(), cell.style)
@@ -187,6 +285,46 @@ impl MemSBLine {
     pub fn iter(&self) -> PieceIter {
         PieceIter::new(self)
     }
+
+    pub fn marks(&self) -> &[LineMark] { &self.marks }
+
+    pub fn bookmark(&self) -> Option<&str> { self.bookmark.as_deref() }
+
+    pub fn size(&self) -> VTLineSize { self.size }
+
+    /// Textual content of the cells `start .. end`, without trailing whitespace.
+    /// Combining characters are counted as part of the preceding cell.
+    pub fn text(&self, start: usize, end: usize) -> String {
+        let mut res = String::new();
+        let mut cell = 0;
+        let mut first = true;
+        let (mut wide, mut placeholder) = (false, false);
+        let chars = self.iter().flat_map(|piece| {
+            let is_wide = piece.style.rendition.contains(VTRendition::WIDE);
+            piece.string.chars().map(move |ch| (ch, is_wide))
+        });
+        for (ch, is_wide) in chars {
+            if ch.width() != Some(0) {
+                if !first {
+                    cell += 1;
+                }
+                // Skip the placeholder cell following a wide character
+                placeholder = wide;
+                wide = is_wide;
+            }
+            first = false;
+            if cell >= end {
+                break;
+            }
+            if cell >= start && !placeholder {
+                res.push(ch);
+            }
+        }
+
+        let trimmed = res.trim_end().len();
+        res.truncate(trimmed);
+        res
+    }
 }
 
 
@@ -225,14 +363,16 @@ impl<'a> iter::Iterator for PieceIter<'a> {
             return None;
         }
 
-        let rend = SBRendition::from_bits_truncate(self.chunk[self.offset]);
-        let size = self.chunk[self.offset + 1] as usize;
+        let rend = SBRendition::memsb_decode(&self.chunk[self.offset ..]);
+        let size = self.chunk[self.offset + SBRendition::SIZE] as usize;
         if rend.is_last() || size == 0 {
             self.last_seen = true;
         }
 
         let mut style = Style::default();
-        let mut data_offset = self.offset + 2;
+        style.rendition |= rend.rendition();
+        style.underline = rend.underline();
+        let mut data_offset = self.offset + SBRendition::SIZE + 1;
         if rend.has_fg() {
             style.col_fg = VTColor::memsb_decode(&self.chunk[data_offset .. data_offset + 4]);
             data_offset += 4;
@@ -241,6 +381,14 @@ impl<'a> iter::Iterator for PieceIter<'a> {
             style.col_bg = VTColor::memsb_decode(&self.chunk[data_offset .. data_offset + 4]);
             data_offset += 4;
         }
+        if rend.has_link() {
+            style.link = LinkId::memsb_decode(&self.chunk[data_offset .. data_offset + 4]);
+            data_offset += 4;
+        }
+        if rend.has_ul() {
+            style.col_ul = Some(VTColor::memsb_decode(&self.chunk[data_offset .. data_offset + 4]));
+            data_offset += 4;
+        }
 
         let str_slice = &self.chunk[data_offset .. data_offset + size];
         let string = unsafe { ::std::str::from_utf8_unchecked(str_slice) };
@@ -267,11 +415,13 @@ pub type LineIter<'a> = vec_deque::Iter<'a, MemSBLine>;
 /// Additionally, allocating memory by larger chunks is used instead of allocation smaller pieces for each line
 /// (currently a chunk size of 32k is used).
 ///
-/// Each piece is layed out in memory as follows:
+/// Each piece is layed out in memory as follows (`length` being the size of the string data in bytes):
 ///
-/// `flags: u8 | length: u8 | [fg_color: u32] | [bg_color: u32] | UTF-8 string data ...`
+/// `flags: u24 | length: u8 | [fg_color: u32] | [bg_color: u32] | [link: u32] | [ul_color: u32] | UTF-8 string data ...`
 ///
-/// The foreground and/or background color is only stored when it differs from the default.
+/// The foreground and/or background color is only stored when it differs from the default,
+/// likewise the hyperlink handle is only stored for pieces that are part of a hyperlink
+/// and the underline color only when it is set.
 #[derive(Debug)]
 pub struct MemScrollback {
     lines: VecDeque<MemSBLine>,
@@ -339,6 +489,15 @@ impl MemScrollback {
     /// Nuber of lines in the scollback.
     pub fn len(&self) -> usize { self.lines.len() }
 
+    /// Get a line by index, 0 being the oldest line
+    pub fn get(&self, index: usize) -> Option<&MemSBLine> { self.lines.get(index) }
+
+    /// Remove all lines
+    pub fn clear(&mut self) {
+        self.lines = VecDeque::new();
+        self.data_size = 0;
+    }
+
     /// Set the memory cap (in bytes) of the scrollback in-memory data storage.
     /// Note that due to internal implementation details the actual comsumed size may be somewhat larger,
     /// although not by a very significant ammount.
@@ -424,6 +583,52 @@ fn memscrollback_basic() {
     }
 }
 
+#[test]
+fn memscrollback_link() {
+    let mut links = ::Hyperlinks::new();
+    let link = links.intern(::VTHyperlink { id: None, uri: "http://example.com".into() });
+    let style_link = Style { link, ..Style::with_fg(VTColor::Indexed(::VTCOLOR_BLUE)) };
+
+    let mut line = Line::new();
+    line.push(Cell::new('a', Style::default()));
+    line.push(Cell::new('b', style_link));
+    line.push(Cell::new('c', Style::default()));
+
+    let mut scrollback = MemScrollback::new(MEM_CAP);
+    scrollback.push(line.clone());
+
+    let pieces: Vec<_> = scrollback.iter().next().unwrap().iter().collect();
+    assert_eq!(pieces[1], Piece { string: "b", style: style_link });
+    assert_eq!(pieces[1].style.link.and_then(|id| links.get(id)).unwrap().uri, "http://example.com");
+    assert_eq!(MemSBLine::line_size(&line), MemSBLine::new(&line).chunk().len());
+}
+
+#[test]
+fn memscrollback_rendition() {
+    let style_curly = Style {
+        underline: ::VTUnderline::Curly,
+        col_ul: Some(VTColor::Rgb(0xff, 0, 0)),
+        ..Style::default()
+    };
+    let style_italic = Style::with_rendition(VTRendition::ITALIC | VTRendition::STRIKE | VTRendition::DIRTY);
+    let style_bold = Style::with_rendition(VTRendition::BOLD | VTRendition::RAPID_BLINK | VTRendition::DIRTY);
+
+    let mut line = Line::new();
+    line.push(Cell::new('a', Style::default()));
+    line.push(Cell::new('b', style_curly));
+    line.push(Cell::new('c', style_italic));
+    line.push(Cell::new('d', style_bold));
+
+    let mut scrollback = MemScrollback::new(MEM_CAP);
+    scrollback.push(line.clone());
+
+    let pieces: Vec<_> = scrollback.iter().next().unwrap().iter().collect();
+    assert_eq!(pieces[1], Piece { string: "b", style: style_curly });
+    assert_eq!(pieces[2], Piece { string: "c", style: style_italic });
+    assert_eq!(pieces[3], Piece { string: "d", style: style_bold });
+    assert_eq!(MemSBLine::line_size(&line), MemSBLine::new(&line).chunk().len());
+}
+
 #[test]
 fn memscrollback_line_size() {
     let lines = vec![
@@ -439,6 +644,25 @@ fn memscrollback_line_size() {
     }
 }
 
+#[test]
+fn memscrollback_combining() {
+    // Combining marks beyond the cell limit are dropped, so piece sizes stay within their byte
+    let mut line = Line::new();
+    line.push(Cell::new('x', Style::default()));
+    let mut cell = Cell::new('a', Style::default());
+    for _ in 0..300 { cell.push('\u{20d0}'); }
+    line.push(cell.clone());
+    line.push(cell.clone());
+    assert!(cell.as_str().len() <= ::CELL_BYTES_MAX);
+
+    let mut scrollback = MemScrollback::new(MEM_CAP);
+    scrollback.push(line.clone());
+
+    let text: String = scrollback.iter().next().unwrap().iter().map(|piece| piece.string).collect();
+    assert_eq!(text, format!("x{}{}", cell.as_str(), cell.as_str()));
+    assert_eq!(MemSBLine::line_size(&line), MemSBLine::new(&line).chunk().len());
+}
+
 #[test]
 fn memscrollback_mem_cap() {
     let tests = vec![
@@ -0,0 +1,290 @@
+/// Sixel graphics decoder
+///
+/// Sixel data is sent in a DCS: `ESC P P1 ; P2 ; P3 q <data> ESC \`.
+/// The data consists of sixel characters (`?` through `~`), each encoding a column of six pixels,
+/// interleaved with control functions:
+///  - `" Pan ; Pad ; Ph ; Pv` raster attributes (aspect ratio and image size),
+///  - `# Pc` color selection, `# Pc ; Pu ; Px ; Py ; Pz` color definition (HLS or RGB),
+///  - `! Pn <sixel>` repeat,
+///  - `$` carriage return, `-` new line (the next band of six pixel rows).
+///
+/// Pixels are always square, the aspect ratio is ignored.
+
+use ::image::{Image, IMAGE_SIZE_MAX};
+
+
+/// Number of color registers
+const REGISTERS: usize = 256;
+
+/// The VT340 default palette, in percent
+const PALETTE_DEFAULT: [(u32, u32, u32); 16] = [
+    (0, 0, 0), (20, 20, 80), (80, 13, 13), (20, 80, 20),
+    (80, 20, 80), (20, 80, 80), (80, 80, 20), (53, 53, 53),
+    (26, 26, 26), (33, 33, 60), (60, 26, 26), (33, 60, 33),
+    (60, 33, 60), (33, 60, 60), (60, 60, 33), (80, 80, 80),
+];
+
+fn percent(value: u32) -> u8 {
+    (value.min(100) * 255 / 100) as u8
+}
+
+/// Convert DEC HLS (hue 0 being blue, in degrees, lightness and saturation in percent) to RGB
+fn hls_to_rgb(h: u32, l: u32, s: u32) -> [u8; 4] {
+    let h = ((h % 360 + 240) % 360) as f32;
+    let l = l.min(100) as f32 / 100.0;
+    let s = s.min(100) as f32 / 100.0;
+
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = l - c / 2.0;
+    let (r, g, b) = match h as u32 / 60 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    let byte = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    [byte(r), byte(g), byte(b), 0xff]
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    Repeat,
+    Raster,
+    Color,
+}
+
+#[derive(Debug)]
+pub struct SixelDecoder {
+    state: State,
+    /// Numeric parameters of the control function being parsed
+    params: Vec<u32>,
+    palette: Vec<[u8; 4]>,
+    color: usize,
+    /// Whether pixels that aren't drawn are transparent (`P2 = 1`) or filled with color 0
+    transparent: bool,
+    /// Position of the next sixel, `y` is the top of the current band
+    x: u32,
+    y: u32,
+    /// Size requested by raster attributes
+    raster: (u32, u32),
+    /// Extent of the drawn pixels
+    extent: (u32, u32),
+    /// Pixel buffer, `stride` pixels per row
+    pixels: Vec<[u8; 4]>,
+    stride: u32,
+}
+
+impl SixelDecoder {
+    /// Create a decoder, `params` are the DCS parameters
+    pub fn new(params: &[i32]) -> SixelDecoder {
+        let mut palette = vec![[0, 0, 0, 0xff]; REGISTERS];
+        for (reg, &(r, g, b)) in palette.iter_mut().zip(PALETTE_DEFAULT.iter()) {
+            *reg = [percent(r), percent(g), percent(b), 0xff];
+        }
+
+        SixelDecoder {
+            state: State::Data,
+            params: vec![],
+            palette,
+            color: 0,
+            transparent: params.get(1) == Some(&1),
+            x: 0,
+            y: 0,
+            raster: (0, 0),
+            extent: (0, 0),
+            pixels: vec![],
+            stride: 0,
+        }
+    }
+
+    /// Grow the pixel buffer to accomodate `width` x `height` pixels, returns `false` if the size is over the limit
+    fn reserve(&mut self, width: u32, height: u32) -> bool {
+        if width > IMAGE_SIZE_MAX || height > IMAGE_SIZE_MAX {
+            return false;
+        }
+
+        let rows = self.pixels.len() as u32 / self.stride.max(1);
+        if width > self.stride {
+            // Re-layout the rows, doubling the stride to amortize the cost
+            let stride = width.max(self.stride * 2).min(IMAGE_SIZE_MAX);
+            let mut pixels = vec![[0; 4]; (stride * rows) as usize];
+            for y in 0 .. rows as usize {
+                let (src, dst) = (y * self.stride as usize, y * stride as usize);
+                pixels[dst .. dst + self.stride as usize].copy_from_slice(&self.pixels[src .. src + self.stride as usize]);
+            }
+            self.pixels = pixels;
+            self.stride = stride;
+        }
+
+        if height > rows {
+            let len = (self.stride * height) as usize;
+            self.pixels.resize(len, [0; 4]);
+        }
+
+        true
+    }
+
+    fn put_sixel(&mut self, byte: u8, count: u32) {
+        let bits = byte - b'?';
+        let count = count.clamp(1, IMAGE_SIZE_MAX);
+        let (x0, x1) = (self.x, self.x + count);
+        self.x = x1.min(IMAGE_SIZE_MAX);
+
+        if bits == 0 || !self.reserve(x1, self.y + 6) {
+            return;
+        }
+
+        let color = self.palette[self.color];
+        for bit in 0 .. 6 {
+            if bits & (1 << bit) != 0 {
+                let row = ((self.y + bit) * self.stride) as usize;
+                for px in &mut self.pixels[row + x0 as usize .. row + x1 as usize] {
+                    *px = color;
+                }
+                self.extent.1 = self.extent.1.max(self.y + bit + 1);
+            }
+        }
+        self.extent.0 = self.extent.0.max(x1);
+    }
+
+    /// Apply the control function whose parameters were just parsed
+    fn end_params(&mut self) {
+        let param = |params: &[u32], i: usize| params.get(i).cloned().unwrap_or(0);
+
+        match self.state {
+            State::Raster => {
+                let (width, height) = (param(&self.params, 2), param(&self.params, 3));
+                if width <= IMAGE_SIZE_MAX && height <= IMAGE_SIZE_MAX {
+                    self.raster = (width, height);
+                }
+            },
+            State::Color => {
+                let reg = param(&self.params, 0) as usize % REGISTERS;
+                let (x, y, z) = (param(&self.params, 2), param(&self.params, 3), param(&self.params, 4));
+                match self.params.get(1) {
+                    Some(1) => self.palette[reg] = hls_to_rgb(x, y, z),
+                    Some(2) => self.palette[reg] = [percent(x), percent(y), percent(z), 0xff],
+                    _ => {},
+                }
+                self.color = reg;
+            },
+            _ => {},
+        }
+    }
+
+    /// Feed sixel data
+    pub fn put(&mut self, byte: u8) {
+        match (self.state, byte) {
+            (State::Data, _) => {},
+            (_, b'0' ... b'9') => {
+                if self.params.is_empty() {
+                    self.params.push(0);
+                }
+                let last = self.params.last_mut().unwrap();
+                *last = last.saturating_mul(10).saturating_add((byte - b'0') as u32);
+                return;
+            },
+            (_, b';') => {
+                if self.params.is_empty() {
+                    self.params.push(0);
+                }
+                if self.params.len() < 8 {
+                    self.params.push(0);
+                }
+                return;
+            },
+            (State::Repeat, b'?' ... b'~') => {
+                let count = self.params.first().cloned().unwrap_or(1);
+                self.put_sixel(byte, count);
+                self.state = State::Data;
+                self.params.clear();
+                return;
+            },
+            _ => {
+                self.end_params();
+                self.state = State::Data;
+                self.params.clear();
+            },
+        }
+
+        match byte {
+            b'?' ... b'~' => self.put_sixel(byte, 1),
+            b'"' => self.state = State::Raster,
+            b'#' => self.state = State::Color,
+            b'!' => self.state = State::Repeat,
+            b'$' => self.x = 0,
+            b'-' => {
+                self.x = 0;
+                self.y = (self.y + 6).min(IMAGE_SIZE_MAX);
+            },
+            _ => {},
+        }
+    }
+
+    /// Finish decoding, returns `None` if the image is empty
+    pub fn finish(mut self) -> Option<Image> {
+        self.end_params();
+
+        let width = self.extent.0.max(self.raster.0);
+        let height = self.extent.1.max(self.raster.1);
+        if width == 0 || height == 0 || !self.reserve(width, height) {
+            return None;
+        }
+
+        let background = if self.transparent { [0; 4] } else { self.palette[0] };
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0 .. height {
+            let row = (y * self.stride) as usize;
+            for px in &self.pixels[row .. row + width as usize] {
+                // Pixels that were drawn are always opaque
+                data.extend_from_slice(if px[3] == 0 { &background } else { px });
+            }
+        }
+
+        Some(Image::new(width, height, data))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(params: &[i32], data: &[u8]) -> Option<Image> {
+        let mut decoder = SixelDecoder::new(params);
+        data.iter().for_each(|b| decoder.put(*b));
+        decoder.finish()
+    }
+
+    #[test]
+    fn sixel_decode() {
+        // Two columns of red, a blank band, one column of blue with the bottom two pixels missing
+        let image = decode(&[0, 1], b"#1;2;100;0;0!2~--#2;2;0;0;100N").unwrap();
+        assert_eq!((image.width(), image.height()), (2, 16));
+        assert_eq!(image.pixel(1, 5), Some([255, 0, 0, 255]));
+        assert_eq!(image.pixel(1, 6), Some([0, 0, 0, 0]));
+        assert_eq!(image.pixel(0, 15), Some([0, 0, 255, 255]));
+        assert_eq!(image.pixel(1, 15), Some([0, 0, 0, 0]));
+
+        // Raster attributes & opaque background, HLS color
+        let image = decode(&[], b"\"1;1;4;3#3;1;120;50;100@$#0A").unwrap();
+        assert_eq!((image.width(), image.height()), (4, 3));
+        assert_eq!(image.pixel(0, 0), Some([255, 0, 0, 255]));
+        assert_eq!(image.pixel(0, 1), Some([0, 0, 0, 255]));
+        assert_eq!(image.pixel(3, 2), Some([0, 0, 0, 255]));
+
+        // Saturated hue, 4294967295 % 360 = 255
+        let image = decode(&[], b"#3;1;4294967295;50;100@").unwrap();
+        assert_eq!(image.pixel(0, 0), Some(hls_to_rgb(255, 50, 100)));
+
+        assert!(decode(&[], b"").is_none());
+        assert!(decode(&[], b"!99999~").is_some());
+    }
+}
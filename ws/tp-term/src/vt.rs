@@ -1,28 +1,48 @@
 use std::ops;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 
 use utf8;
+use base64;
 
 
 // TODO: comment
 // https://vt100.net/emu/dec_ansi_parser
 
-// XXX: OSC may be terminated by bell (xterm extension?)
+// Note: OSC may also be terminated by BEL instead of ST (xterm extension)
+
+
+/// Maximum size of a control string payload (OSC et al.), excess data is discarded
+const STRING_MAX: usize = 1024 * 1024;
+
+/// Maximum length of a hyperlink URI (OSC 8), longer links are ignored
+pub const HYPERLINK_URI_MAX: usize = 2083;
+/// Maximum length of a hyperlink id (OSC 8), longer ids are ignored
+pub const HYPERLINK_ID_MAX: usize = 250;
 
 
 bitflags! {
-    pub struct VTRendition: u8 {
-        const BOLD       = 1 << 0;
-        const UNDERLINED = 1 << 1;
-        const INVERSE    = 1 << 2;
+    pub struct VTRendition: u16 {
+        const BOLD        = 1 << 0;
+        /// Faint, decreased intensity
+        const DIM         = 1 << 1;
+        const INVERSE     = 1 << 2;
         /// Blinking is sometimes implemented as synonimous to bold.
-        const BLINKING   = 1 << 3;
-        const INVISIBLE  = 1 << 4;
-        const ALL = 0x1f;
+        const BLINKING    = 1 << 3;
+        const INVISIBLE   = 1 << 4;
+        const ITALIC      = 1 << 5;
+        /// Crossed-out text
+        const STRIKE      = 1 << 6;
+        const OVERLINE    = 1 << 7;
+        /// Blinking at 150 per minute or more, may be rendered the same as `BLINKING`
+        const RAPID_BLINK = 1 << 8;
+        const ALL = 0x1ff;
 
         /// Marks a wide unicode character
-        const WIDE       = 1 << 5;
+        const WIDE        = 1 << 14;
         /// For rendering purposes
-        const DIRTY      = 1 << 6;
+        const DIRTY       = 1 << 15;
     }
 }
 
@@ -32,6 +52,65 @@ impl Default for VTRendition {
     }
 }
 
+/// Underline style, set by SGR `4` and `4:n`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTUnderline {
+    None,
+    Single,
+    Double,
+    /// Wavy underline, typically used for spelling errors and diagnostics
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl VTUnderline {
+    fn from_param(p: i32) -> Option<VTUnderline> {
+        match p {
+            0 => Some(VTUnderline::None),
+            1 => Some(VTUnderline::Single),
+            2 => Some(VTUnderline::Double),
+            3 => Some(VTUnderline::Curly),
+            4 => Some(VTUnderline::Dotted),
+            5 => Some(VTUnderline::Dashed),
+            _ => None,
+        }
+    }
+}
+
+impl Default for VTUnderline {
+    fn default() -> VTUnderline { VTUnderline::None }
+}
+
+/// Cursor shape as set by DECSCUSR, blinking is a separate mode (`VTMode::CURSOR_BLINK`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTCursorShape {
+    Block,
+    Underline,
+    /// Vertical bar, typically used for insert mode in editors
+    Bar,
+}
+
+impl Default for VTCursorShape {
+    fn default() -> VTCursorShape { VTCursorShape::Block }
+}
+
+/// Line size attribute as set by DECSWL, DECDWL and DECDHL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTLineSize {
+    Single,
+    /// Characters are twice as wide, only the left half of the columns can be used
+    DoubleWidth,
+    /// Top half of double-height (and double-width) characters
+    DoubleTop,
+    /// Bottom half of double-height (and double-width) characters
+    DoubleBottom,
+}
+
+impl Default for VTLineSize {
+    fn default() -> VTLineSize { VTLineSize::Single }
+}
+
 pub const VTCOLOR_BLACK   : u8 = 0;
 pub const VTCOLOR_RED     : u8 = 1;
 pub const VTCOLOR_GREEN   : u8 = 2;
@@ -52,9 +131,14 @@ pub enum VTColor {
 }
 
 impl VTColor {
-    fn parse<I: Iterator<Item=i32>>(p: i32, it: &mut I) -> Result<VTColor, ()> {
+    /// Parse an SGR color from parameter group `group`, `58` (underline color) only takes extended colors.
+    /// Extended colors (`38`, `48`) may either use subparameters (`38:5:n`, `38:2::r:g:b`)
+    /// or the legacy form with the values in the following parameters (`38;5;n`, `38;2;r;g;b`),
+    /// in which case these are consumed from `it`.
+    fn parse<'a, I: Iterator<Item=&'a [i32]>>(group: &[i32], it: &mut I) -> Result<VTColor, ()> {
         use VTColor::*;
 
+        let p = group[0];
         match p {
             39 => return Ok(DefaultFg),
             49 => return Ok(DefaultBg),
@@ -68,29 +152,42 @@ impl VTColor {
             100 ... 107 => return Ok(Indexed(p as u8 - 100 + 8)),
 
             // Extended color palettes, fall through to parsing below
-            38 | 48 => {},
+            38 | 48 | 58 => {},
 
             _ => return Err(()),
         }
 
-        // We need at least 2 params
-        let op = it.next().ok_or(())?;
-        let p1 = it.next().ok_or(())?;
-
-        // Color cube format
-        match (p, op, p1) {
-            (38, 5, 0 ... 255) => return Ok(Indexed(p1 as u8)),
-            (48, 5, 0 ... 255) => return Ok(Indexed(p1 as u8)),
-            _ => {},
+        if group.len() > 1 {
+            // Subparameter form, in the RGB case the color space id may be omitted
+            match group[1 ..] {
+                [5, n] => VTColor::indexed(n),
+                [2, _, r, g, b, ..] => VTColor::rgb(r, g, b),
+                [2, r, g, b] => VTColor::rgb(r, g, b),
+                _ => Err(()),
+            }
+        } else {
+            let mut next = || it.next().map(|g| g[0]).ok_or(());
+            match next()? {
+                5 => VTColor::indexed(next()?),
+                2 => {
+                    let (r, g, b) = (next()?, next()?, next()?);
+                    VTColor::rgb(r, g, b)
+                },
+                _ => Err(()),
+            }
         }
+    }
 
-        // For RGB format, we need 2 more params
-        let p2 = it.next().ok_or(())?;
-        let p3 = it.next().ok_or(())?;
+    fn indexed(n: i32) -> Result<VTColor, ()> {
+        match n {
+            0 ... 255 => Ok(VTColor::Indexed(n as u8)),
+            _ => Err(()),
+        }
+    }
 
-        match (p, op, p1, p2, p3) {
-            (38, 2, 0 ... 255, 0 ... 255, 0 ... 255) => Ok(Rgb(p1 as u8, p2 as u8, p3 as u8)),
-            (48, 2, 0 ... 255, 0 ... 255, 0 ... 255) => Ok(Rgb(p1 as u8, p2 as u8, p3 as u8)),
+    fn rgb(r: i32, g: i32, b: i32) -> Result<VTColor, ()> {
+        match (r, g, b) {
+            (0 ... 255, 0 ... 255, 0 ... 255) => Ok(VTColor::Rgb(r as u8, g as u8, b as u8)),
             _ => Err(()),
         }
     }
@@ -108,14 +205,65 @@ pub enum VTCharset {
     UsAscii,
     /// Encoded '0', glyphs used for drawing windows, typically in curses-based programs.
     Graphics,
+    /// Encoded 'A', ASCII with the pound sign in place of `#`
+    Uk,
+    /// Encoded '%5' or '<', mostly the same as the right half of ISO 8859-1
+    DecSupplemental,
+    /// Encoded '>', mathematical symbols, Greek letters and pieces for drawing large symbols
+    DecTechnical,
+    /// Encoded 'A' as a 96-character set, the right half of ISO 8859-1
+    Latin1,
+    /// National replacement character set, ASCII with some of the characters replaced
+    Nrcs(VTNrcs),
+}
+
+/// National replacement character sets, see `VTCharset::Nrcs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTNrcs {
+    Dutch,
+    Finnish,
+    French,
+    FrenchCanadian,
+    German,
+    Italian,
+    NorwegianDanish,
+    Portuguese,
+    Spanish,
+    Swedish,
+    Swiss,
 }
 
 impl VTCharset {
-    fn decode(byte: u8) -> Option<VTCharset> {
+    /// Decodes the final byte of a 94-character set designation, `interm` is the intermediate byte
+    /// preceding it, if any
+    fn decode94(interm: u8, byte: u8) -> Option<VTCharset> {
         use VTCharset::*;
-        match byte {
-            b'0' => Some(Graphics),
-            b'B' => Some(UsAscii),
+        use VTNrcs::*;
+        Some(match (interm, byte) {
+            (0, b'B') => UsAscii,
+            (0, b'0') => Graphics,
+            (0, b'A') => Uk,
+            (0, b'<') | (b'%', b'5') => DecSupplemental,
+            (0, b'>') => DecTechnical,
+            (0, b'4') => Nrcs(Dutch),
+            (0, b'C') | (0, b'5') => Nrcs(Finnish),
+            (0, b'R') | (0, b'f') => Nrcs(French),
+            (0, b'Q') | (0, b'9') => Nrcs(FrenchCanadian),
+            (0, b'K') => Nrcs(German),
+            (0, b'Y') => Nrcs(Italian),
+            (0, b'E') | (0, b'6') | (0, b'`') => Nrcs(NorwegianDanish),
+            (b'%', b'6') => Nrcs(Portuguese),
+            (0, b'Z') => Nrcs(Spanish),
+            (0, b'H') | (0, b'7') => Nrcs(Swedish),
+            (0, b'=') => Nrcs(Swiss),
+            _ => return None,
+        })
+    }
+
+    /// Decodes the final byte of a 96-character set designation
+    fn decode96(interm: u8, byte: u8) -> Option<VTCharset> {
+        match (interm, byte) {
+            (0, b'A') => Some(VTCharset::Latin1),
             _ => None,
         }
     }
@@ -128,7 +276,7 @@ impl Default for VTCharset {
 }
 
 bitflags! {
-    pub struct VTMode: u8 {
+    pub struct VTMode: u32 {
         const WRAP         = 1 << 0;
         const ORIGIN       = 1 << 1;
         const NEWLINE      = 1 << 2;
@@ -136,12 +284,272 @@ bitflags! {
         const REVERSE_VIDEO = 1 << 4;
         /// Application cursor keys, aka DECCKM
         const APP_CURSOR_KEYS = 1 << 5;
+
+        /// Mouse tracking, only one of these is active at a time:
+        /// X10 compatibility mode, only button presses are reported
+        const MOUSE_X10    = 1 << 6;
+        /// Button presses and releases are reported
+        const MOUSE_NORMAL = 1 << 7;
+        /// Additionally to `MOUSE_NORMAL`, motion is reported while a button is pressed
+        const MOUSE_BUTTON = 1 << 8;
+        /// Additionally to `MOUSE_NORMAL`, all motion is reported
+        const MOUSE_ANY    = 1 << 9;
+        const MOUSE_TRACKING = Self::MOUSE_X10.bits | Self::MOUSE_NORMAL.bits
+                             | Self::MOUSE_BUTTON.bits | Self::MOUSE_ANY.bits;
+
+        /// Mouse report encoding, the default X10 encoding is used if none of these is set:
+        const MOUSE_UTF8   = 1 << 10;
+        const MOUSE_SGR    = 1 << 11;
+        const MOUSE_URXVT  = 1 << 12;
+        /// Like `MOUSE_SGR` but with positions in pixels
+        const MOUSE_SGR_PIXELS = 1 << 13;
+        const MOUSE_ENCODING = Self::MOUSE_UTF8.bits | Self::MOUSE_SGR.bits
+                             | Self::MOUSE_URXVT.bits | Self::MOUSE_SGR_PIXELS.bits;
+
+        /// Pasted text is enclosed in `ESC [ 200 ~` and `ESC [ 201 ~`
+        const BRACKETED_PASTE = 1 << 14;
+        /// Focus changes are reported as `CSI I` and `CSI O`
+        const FOCUS_EVENTS = 1 << 15;
+
+        /// Text cursor enable mode, aka DECTCEM
+        const CURSOR_VISIBLE = 1 << 16;
+        const CURSOR_BLINK   = 1 << 17;
+
+        /// Synchronized output, the program is drawing a frame which shouldn't be displayed until complete
+        const SYNC_OUTPUT    = 1 << 18;
+        /// The alternate screen is active
+        const ALT_SCREEN     = 1 << 19;
+        /// Left and right margins can be set, aka DECLRMM
+        const LR_MARGINS     = 1 << 20;
+    }
+}
+
+/// How a mode set with SM / DECSET is tracked, see `VTMode::from_number()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTModeKind {
+    /// The mode is tracked as these `VTMode` bits
+    Mode(VTMode),
+    /// The mode is recognized but can't be changed
+    PermanentlySet,
+    PermanentlyReset,
+}
+
+impl VTMode {
+    /// Looks up a mode by its number, `private` for DEC private modes (DECSET), ANSI modes (SM) otherwise.
+    /// Returns `None` for modes that aren't recognized.
+    pub fn from_number(private: bool, number: u32) -> Option<VTModeKind> {
+        use self::VTModeKind::*;
+
+        Some(match (private, number) {
+            (false, 4) => Mode(VTMode::INSERT),
+            (false, 12) => PermanentlySet,      // SRM, we never echo locally
+            (false, 20) => Mode(VTMode::NEWLINE),
+
+            (true, 1) => Mode(VTMode::APP_CURSOR_KEYS),
+            (true, 2) => PermanentlySet,        // DECANM, there's no VT52 mode
+            (true, 3) => PermanentlyReset,      // DECCOLM, there's no 132 column mode
+            (true, 4) => PermanentlyReset,      // DECSCLM, there's no smooth scrolling
+            (true, 5) => Mode(VTMode::REVERSE_VIDEO),
+            (true, 6) => Mode(VTMode::ORIGIN),
+            (true, 7) => Mode(VTMode::WRAP),
+            (true, 9) => Mode(VTMode::MOUSE_X10),
+            (true, 12) => Mode(VTMode::CURSOR_BLINK),
+            (true, 25) => Mode(VTMode::CURSOR_VISIBLE),
+            (true, 47) | (true, 1047) | (true, 1049) => Mode(VTMode::ALT_SCREEN),
+            (true, 69) => Mode(VTMode::LR_MARGINS),
+            (true, 1000) => Mode(VTMode::MOUSE_NORMAL),
+            (true, 1002) => Mode(VTMode::MOUSE_BUTTON),
+            (true, 1003) => Mode(VTMode::MOUSE_ANY),
+            (true, 1004) => Mode(VTMode::FOCUS_EVENTS),
+            (true, 1005) => Mode(VTMode::MOUSE_UTF8),
+            (true, 1006) => Mode(VTMode::MOUSE_SGR),
+            (true, 1015) => Mode(VTMode::MOUSE_URXVT),
+            (true, 1016) => Mode(VTMode::MOUSE_SGR_PIXELS),
+            (true, 2004) => Mode(VTMode::BRACKETED_PASTE),
+            (true, 2026) => Mode(VTMode::SYNC_OUTPUT),
+
+            _ => return None,
+        })
     }
 }
 
 impl Default for VTMode {
     fn default() -> VTMode {
-        VTMode::WRAP
+        VTMode::WRAP | VTMode::CURSOR_VISIBLE | VTMode::CURSOR_BLINK
+    }
+}
+
+/// Hyperlink as specified with OSC 8
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VTHyperlink {
+    /// Optional link identifier, used to group cells of the same link that aren't adjacent
+    pub id: Option<String>,
+    pub uri: String,
+}
+
+impl VTHyperlink {
+    /// Parses OSC 8 arguments, ie. `params ; URI` where `params` is a `:`-separated list of `key=value` pairs.
+    /// Returns `None` for an empty or too long URI, which terminates the current hyperlink.
+    fn parse(arg: &str) -> Option<VTHyperlink> {
+        let mut split = arg.splitn(2, ';');
+        let params = split.next().unwrap_or("");
+        let uri = split.next().unwrap_or("");
+
+        if uri.is_empty() || uri.len() > HYPERLINK_URI_MAX {
+            return None;
+        }
+
+        let id = params.split(':')
+            .filter_map(|param| {
+                let mut kv = param.splitn(2, '=');
+                match (kv.next(), kv.next()) {
+                    (Some("id"), Some(id)) if !id.is_empty() && id.len() <= HYPERLINK_ID_MAX => Some(id.to_string()),
+                    _ => None,
+                }
+            })
+            .next();
+
+        Some(VTHyperlink { id, uri: uri.to_string() })
+    }
+}
+
+/// Selection (clipboard) targets of OSC 52
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTSelection {
+    /// The clipboard, encoded as `c`
+    Clipboard,
+    /// The primary selection, encoded as `p` or `s`
+    Primary,
+}
+
+impl VTSelection {
+    fn decode(byte: u8) -> Option<VTSelection> {
+        match byte {
+            b'c' => Some(VTSelection::Clipboard),
+            b'p' | b's' => Some(VTSelection::Primary),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> u8 {
+        match *self {
+            VTSelection::Clipboard => b'c',
+            VTSelection::Primary => b'p',
+        }
+    }
+}
+
+/// Palette entries that can be set and queried at runtime (OSC 4, OSC 10 - 12)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTPaletteSlot {
+    Indexed(u8),
+    Foreground,
+    Background,
+    Cursor,
+}
+
+impl VTPaletteSlot {
+    /// Dynamic colors are addressed by the OSC number, starting with OSC 10 for foreground
+    fn dynamic(num: u32) -> Option<VTPaletteSlot> {
+        match num {
+            10 => Some(VTPaletteSlot::Foreground),
+            11 => Some(VTPaletteSlot::Background),
+            12 => Some(VTPaletteSlot::Cursor),
+            _ => None,
+        }
+    }
+}
+
+/// Parses an X11 color specification, ie. either `rgb:r/g/b` with 1 to 4 hex digits per component
+/// or `#rgb` with 1 to 4 hex digits per component, without separators.
+/// Returns the color scaled to 8 bits per component.
+fn parse_color_spec(spec: &str) -> Option<(u8, u8, u8)> {
+    fn component(hex: &str) -> Option<u8> {
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let max = (1u32 << (4 * hex.len())) - 1;
+        Some((value * 255 / max) as u8)
+    }
+
+    if let Some(rgb) = spec.strip_prefix("rgb:") {
+        let mut it = rgb.split('/').map(component);
+        match (it.next(), it.next(), it.next(), it.next()) {
+            (Some(r), Some(g), Some(b), None) => Some((r?, g?, b?)),
+            _ => None,
+        }
+    } else if let Some(hex) = spec.strip_prefix('#') {
+        let n = hex.len() / 3;
+        if n == 0 || hex.len() % 3 != 0 || !hex.is_char_boundary(n) || !hex.is_char_boundary(2 * n) {
+            return None;
+        }
+        Some((component(&hex[..n])?, component(&hex[n .. 2 * n])?, component(&hex[2 * n ..])?))
+    } else {
+        None
+    }
+}
+
+/// Decodes `%XX` escapes, malformed escapes are kept as they are
+fn percent_decode(s: &str) -> Vec<u8> {
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|d| d as u8);
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1).cloned().and_then(hex), bytes.get(i + 2).cloned().and_then(hex)) {
+            (b'%', Some(hi), Some(lo)) => {
+                res.push(hi << 4 | lo);
+                i += 3;
+            },
+            (byte, _, _) => {
+                res.push(byte);
+                i += 1;
+            },
+        }
+    }
+
+    res
+}
+
+/// Parses a `file://host/path` URI as used by OSC 7, returns the host and the decoded path
+fn parse_file_uri(uri: &str) -> Option<(&str, PathBuf)> {
+    if !uri.starts_with("file://") {
+        return None;
+    }
+
+    let uri = &uri[7..];
+    let slash = uri.find('/')?;
+    let path = OsString::from_vec(percent_decode(&uri[slash..]));
+    Some((&uri[..slash], PathBuf::from(path)))
+}
+
+/// Shell integration marks (OSC 133)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTSemanticMark {
+    /// `A`: Start of the prompt
+    PromptStart,
+    /// `B`: End of the prompt, start of the command line typed in by the user
+    CommandStart,
+    /// `C`: The command was executed, start of its output
+    OutputStart,
+    /// `D`: The command has finished, with an exit code if the shell reported it
+    CommandFinished(Option<i32>),
+}
+
+impl VTSemanticMark {
+    fn parse(arg: &str) -> Option<VTSemanticMark> {
+        use VTSemanticMark::*;
+
+        let mut it = arg.split(';');
+        match it.next()? {
+            "A" => Some(PromptStart),
+            "B" => Some(CommandStart),
+            "C" => Some(OutputStart),
+            "D" => Some(CommandFinished(it.next().and_then(|code| code.parse().ok()))),
+            _ => None,
+        }
     }
 }
 
@@ -157,37 +565,64 @@ pub enum VTErase {
 }
 
 
+/// Maximum number of parameters (including subparameters) kept, excess ones are dropped
+const PARAMS_MAX: usize = 32;
+
+/// CSI / DCS parameters
+///
+/// Parameters are separated by `;`, a parameter may be followed by subparameters separated by `:`
+/// (eg. `38:2::255:0:0`). Subparameters are stored in `ints` just like parameters,
+/// `subs` marks which of the entries are subparameters.
 #[derive(Debug)]
 struct Params {
     ints: Vec<i32>,
+    /// Bit `i` set means `ints[i]` is a subparameter of the preceding parameter
+    subs: u32,
     open: bool,
+    /// Whether the next parameter is a subparameter
+    sub_next: bool,
 }
 
 impl Params {
     fn new() -> Params {
         Params {
             ints: vec![],
+            subs: 0,
             open: false,
+            sub_next: false,
         }
     }
 
-    fn push_digit(&mut self, digit: u8) {
-        if self.ints.len() > 16 { return; }
+    fn push(&mut self, num: i32) {
+        if self.ints.len() >= PARAMS_MAX { return; }
+
+        if self.sub_next {
+            self.subs |= 1 << self.ints.len();
+        }
+        self.ints.push(num);
+    }
 
-        if !self.open { self.ints.push(0); }
+    fn push_digit(&mut self, digit: u8) {
+        if !self.open {
+            if self.ints.len() >= PARAMS_MAX { return; }
+            self.push(0);
+            self.open = true;
+        }
 
         let last = self.ints.last_mut().unwrap();
         if let Some(num) = last.checked_mul(10).and_then(|num| num.checked_add(digit as i32)) {
             *last = num;
         }
-
-        self.open = true;
     }
 
-    fn next(&mut self) {
-        if self.open && self.ints.len() <= 16 {
-            self.open = false;
+    /// Finish the current parameter, `sub` tells whether the separator was a `:`.
+    /// An empty parameter is recorded as `0`.
+    fn next(&mut self, sub: bool) {
+        if !self.open {
+            self.push(0);
         }
+        self.open = false;
+        self.sub_next = sub;
     }
 
     fn get(&self, index: usize, default: i32) -> i32 {
@@ -197,9 +632,48 @@ impl Params {
         }
     }
 
+    /// Whether any `:` separator was seen
+    fn has_subs(&self) -> bool {
+        self.subs != 0 || self.sub_next
+    }
+
+    /// Iterate over parameters, each together with its subparameters
+    fn groups(&self) -> ParamGroups<'_> {
+        ParamGroups {
+            params: self,
+            pos: 0,
+        }
+    }
+
     fn clear(&mut self) {
         self.ints.clear();
+        self.subs = 0;
         self.open = false;
+        self.sub_next = false;
+    }
+}
+
+struct ParamGroups<'a> {
+    params: &'a Params,
+    pos: usize,
+}
+
+impl<'a> Iterator for ParamGroups<'a> {
+    type Item = &'a [i32];
+
+    fn next(&mut self) -> Option<&'a [i32]> {
+        let ints = &self.params.ints;
+        if self.pos >= ints.len() {
+            return None;
+        }
+
+        let start = self.pos;
+        self.pos += 1;
+        while self.pos < ints.len() && self.params.subs & (1 << self.pos) != 0 {
+            self.pos += 1;
+        }
+
+        Some(&ints[start .. self.pos])
     }
 }
 
@@ -227,15 +701,35 @@ enum State {
     CsiIgnore,
 
     // Control string states
+    OscString,
+    DcsEntry,
+    DcsParam,
+    DcsInterm,
+    DcsPassthrough,
+    DcsIgnore,
     ApcEntry,
     ApcInterm,
     ApcTp,
+    ApcGraphics,
     CtrlStrIgnore,
 }
 
 use self::State::*;
 
+/// Settings that can be requested with DECRQSS
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTSetting {
+    /// SGR, ie. the current character rendition
+    Sgr,
+    /// DECSTBM, ie. the scrolling region
+    ScrollRegion,
+    /// DECSCUSR, ie. the cursor style
+    CursorStyle,
+    /// A setting that we don't recognize or support
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VTReport {
     AnswerBack,
     PrimaryAttrs,
@@ -244,6 +738,42 @@ pub enum VTReport {
     CursorPos,
     TermParams0,
     TermParams1,
+    /// Selection content requested by OSC 52. Not answered by `report_answer()`, the application
+    /// must answer with the selection content using `clipboard_answer()`.
+    Clipboard(VTSelection),
+    /// Setting requested by DECRQSS
+    Setting(VTSetting),
+    /// Terminfo capability requested by XTGETTCAP
+    TermCap(String),
+    /// Kitty graphics protocol response, ie. the APC payload
+    Graphics(String),
+    /// Mode state requested by DECRQM, `private` tells DEC private modes apart from ANSI modes
+    Mode { private: bool, number: u32 },
+    /// Size of the text area in pixels (XTWINOPS 14)
+    TextAreaPixels,
+    /// Size of a cell in pixels (XTWINOPS 16)
+    CellPixels,
+    /// Size of the text area in cells (XTWINOPS 18)
+    TextAreaCells,
+}
+
+/// Window manipulation requested with XTWINOPS, the application may or may not carry these out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VTWindowRequest {
+    /// Iconify (minimize) if `true`, de-iconify otherwise
+    Iconify(bool),
+    /// Raise the window to the front if `true`, lower it otherwise
+    Raise(bool),
+    /// Move the window to the position x, y in pixels
+    Move(u32, u32),
+    /// Resize the text area to width, height in pixels, `0` keeps the current value
+    Resize(u32, u32),
+    /// Resize the text area to columns, rows, `0` keeps the current value
+    ResizeCells(u32, u32),
+    /// Maximize if `true`, restore otherwise
+    Maximize(bool),
+    /// Enter full screen if `true`, leave it otherwise
+    Fullscreen(bool),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -261,9 +791,15 @@ impl Default for VTScreenChoice {
 pub trait VTScreen {
     /// Insert a unicode charater
     fn put_char(&mut self, ch: char);
-    /// Insert `num` default (empty) characters
-    fn put_chars(&mut self, num: u32);
+    /// Insert `num` blank characters at the cursor, shifting the rest of the line right (ICH)
+    fn insert_chars(&mut self, num: u32);
+    /// Delete `num` characters at the cursor, shifting the rest of the line left (DCH)
+    fn delete_chars(&mut self, num: u32);
+    /// Repeat the last printed graphic character `num` times (REP)
+    fn repeat_char(&mut self, num: u32);
     fn newline(&mut self);
+    /// Move the cursor to the left margin, or to the first column if it's left of the margin
+    fn carriage_return(&mut self);
     fn index(&mut self, forward: bool);
     fn next_line(&mut self);
     /// Performs an erase operation pertaining to current cursor location.
@@ -275,6 +811,7 @@ pub trait VTScreen {
     fn tab_set(&mut self, tab: bool);
     fn tabs_clear(&mut self);
 
+    /// Reset the screen to its initial state, except for the scrollback, see `VTDispatch::full_reset()`
     fn reset(&mut self);
 
     /// Scroll screen or the scrolling region if any.
@@ -292,21 +829,37 @@ pub trait VTScreen {
     /// Additionally, `bottom` should be strictly larger than `top`, and `top` should be less than screen's height.
     /// If those conditions are not met, apply the default action - reseting the scroll region to the whole screen.
     /// Note that `1` means "the first line" (ie. 1-indexing).
-    /// This also resets cursor to the top-left corner.
+    /// This also resets cursor to the home position.
     fn set_scroll_region(&mut self, top: u32, bottom: u32);
 
+    /// Set left and right margins, only applicable while `VTMode::LR_MARGINS` is set.
+    /// Defaults and sanitization are the same as for `set_scroll_region()`, and the cursor is likewise reset.
+    fn set_margins(&mut self, left: u32, right: u32);
+
     fn set_mode(&mut self, mode: VTMode, enable: bool);
     fn set_rendition(&mut self, rend: VTRendition, enable: bool);
+    fn set_underline(&mut self, underline: VTUnderline);
+    /// Set the color of underlines, `None` means the foreground color is used
+    fn set_underline_color(&mut self, color: Option<VTColor>);
     fn set_fg(&mut self, color: VTColor);
     fn set_bg(&mut self, color: VTColor);
+    /// Set the hyperlink to be applied to subsequently written characters, `None` ends the current hyperlink
+    fn set_hyperlink(&mut self, link: Option<VTHyperlink>);
+    /// Record a shell integration mark at the current cursor position
+    fn semantic_mark(&mut self, mark: VTSemanticMark);
 
+    /// Invoke the charset designated to the `slot` (G0 through G3) into GL until further notice (locking shift)
     fn charset_use(&mut self, slot: u32);
+    /// Invoke the charset designated to the `slot` into GR (locking shift), which only applies to 8-bit bytes
+    fn charset_use_gr(&mut self, slot: u32);
+    /// Use the charset designated to the `slot` for the next printed character only (single shift)
+    fn charset_single_shift(&mut self, slot: u32);
     fn charset_designate(&mut self, slot: u32, charset: VTCharset);
 
     /// Get current cursor coordinates, horizontal and vertical respectively, 1-indexed
     fn cursor(&self) -> (u32, u32);
     /// Set cursor absolute position
-    /// Note that the coordinates are 1-indexed.
+    /// Note that the coordinates are 1-indexed. In origin mode, they are relative to the scrolling region and margins.
     fn cursor_set(&mut self, x: Option<u32>, y: Option<u32>);
     /// Set cursor relative position
     fn cursor_move(&mut self, x: i32, y: i32);
@@ -321,6 +874,8 @@ pub trait VTScreen {
 
     /// "DEC Screen Alignment Test ", actually means the whole screen is filled with `E`s (with default style).
     fn alignment_test(&mut self);
+    /// Set the size attribute of the current line, see `VTLineSize`
+    fn set_line_size(&mut self, size: VTLineSize);
 }
 
 pub trait VTDispatch {
@@ -338,20 +893,96 @@ pub trait VTDispatch {
     /// Reference the alternate screen
     fn screen_alternate(&mut self) -> &mut Self::Screen;
 
+    /// Currently active modes
+    fn mode(&self) -> VTMode;
+
     /// Set current screen
     fn switch_screen(&mut self, screen: VTScreenChoice);
 
     /// Set mode (mode is mirrored to both screens)
     fn set_mode(&mut self, mode: VTMode, enable: bool);
 
+    /// Push the current state of DEC private mode `number` on the mode's save stack (XTSAVE).
+    /// Modes that aren't tracked in `VTMode` are ignored.
+    fn mode_save(&mut self, number: u32);
+
+    /// Pop the state of DEC private mode `number` from the mode's save stack (XTRESTORE), if any
+    fn mode_restore(&mut self, number: u32) -> Option<bool>;
+
+    /// Full reset (RIS): both screens, modes, the palette, the title etc. are reset to their initial state
+    fn full_reset(&mut self);
+
+    /// Soft reset (DECSTR): resets modes, the scrolling region, character sets and the rendition,
+    /// but keeps the screen content and the cursor position
+    fn soft_reset(&mut self);
+
+    /// Set cursor shape (mirrored to both screens)
+    fn set_cursor_shape(&mut self, shape: VTCursorShape);
+
     /// Enqueue a terminal report request
     fn report_request(&mut self, report: VTReport);
 
     /// Enqueue a bell signal in the current shell
     fn bell(&mut self);
 
-    // TP extensions:
-    // TODO
+    /// Set the window title (OSC 0 and OSC 2)
+    fn set_title(&mut self, title: &str);
+
+    /// Set the icon name, ie. the short title used for tabs and taskbars (OSC 0 and OSC 1)
+    fn set_icon_name(&mut self, name: &str);
+
+    /// Save the icon name and/or the title on the title stack (XTWINOPS 22)
+    fn title_push(&mut self, icon_name: bool, title: bool);
+
+    /// Restore the icon name and/or the title from the title stack (XTWINOPS 23)
+    fn title_pop(&mut self, icon_name: bool, title: bool);
+
+    /// Enqueue a window manipulation request (XTWINOPS)
+    fn window_request(&mut self, request: VTWindowRequest);
+
+    /// Set the current working directory of the shell (OSC 7).
+    /// `host` is the hostname as reported by the shell, may be empty.
+    fn set_working_dir(&mut self, host: &str, path: &Path);
+
+    /// Store `data` into a selection (OSC 52)
+    fn clipboard_store(&mut self, selection: VTSelection, data: &str);
+
+    /// Request content of a selection to be reported back (OSC 52 query)
+    fn clipboard_load(&mut self, selection: VTSelection);
+
+    /// Change a palette color (OSC 4, OSC 10 - 12)
+    fn palette_set(&mut self, slot: VTPaletteSlot, rgb: (u8, u8, u8));
+
+    /// Reset a palette color to its default, `None` resets all indexed colors (OSC 104, OSC 110 - 112)
+    fn palette_reset(&mut self, slot: Option<VTPaletteSlot>);
+
+    /// Request a palette color to be reported back (OSC 4, OSC 10 - 12 with `?`)
+    fn palette_query(&mut self, slot: VTPaletteSlot);
+
+    /// Start of a DCS control string. `private` is the private marker (`<`, `=`, `>` or `?`) if any,
+    /// `interm` the intermediate byte if any, and `action` the final byte.
+    /// The control string data follows via `dcs_put()` and is terminated by `dcs_unhook()`.
+    fn dcs_hook(&mut self, private: u8, interm: u8, params: &[i32], action: u8);
+
+    /// DCS control string data
+    fn dcs_put(&mut self, byte: u8);
+
+    /// End of a DCS control string, `complete` is false if the string was canceled (by CAN or SUB)
+    fn dcs_unhook(&mut self, complete: bool);
+
+    /// Kitty graphics protocol command (`ESC _ G <data> ESC \`)
+    fn kitty_graphics(&mut self, data: &[u8]);
+
+    // TP extensions (`ESC _ T P`), see `Dispatcher::apc_tp_dispatch()`:
+
+    /// Set a per-session user variable, `None` unsets it
+    fn tp_set_user_var(&mut self, name: &str, value: Option<&str>);
+
+    /// Bookmark the cursor line
+    fn tp_bookmark(&mut self, label: &str);
+
+    /// Ask the application to open a new tab
+    fn tp_new_tab(&mut self, working_dir: Option<&Path>);
 }
 
 #[derive(Debug)]
@@ -361,6 +992,8 @@ pub struct VTParser {
     interm1: u8,
     interm2: u8,
     params: Params,
+    /// Control string payload
+    data: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -387,17 +1020,30 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
         match byte {
             0x18 | 0x1a => {
                 // A previous escape sequence, if there was one, gets canceled
+                if self.p.state == DcsPassthrough {
+                    self.d.dcs_unhook(false);
+                }
                 self.clear();
                 return Some(Ground);
             },
-            0x1b => return Some(Escape),    // TODO: dispatch ApcTp here ???
+            0x1b => {
+                // ESC terminates control strings, typically as part of the ST (ESC \) sequence
+                self.ctrl_str_end();
+                return Some(Escape);
+            },
+            7 if self.p.state == OscString => {
+                // BEL terminates OSC as well (xterm extension)
+                self.ctrl_str_end();
+                return Some(Ground);
+            },
             _ => {},
         }
 
         // The rest of C0 is interpreted in basic escapes and CSIs, but not in control string sequences (ie. DCS, APC et al.)
         // So we first check for that, then the C0 char executes, and then the escape sequence continues (if any).
         match self.p.state {
-            ApcEntry | ApcInterm | ApcTp | CtrlStrIgnore => return None,
+            OscString | DcsEntry | DcsParam | DcsInterm | DcsPassthrough | DcsIgnore
+            | ApcEntry | ApcInterm | ApcTp | ApcGraphics | CtrlStrIgnore => return None,
             _ => {},
         }
 
@@ -410,7 +1056,7 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
             8 /* BS */  => self.screen().cursor_move(-1, 0),
             9 /* HT */  => self.screen().tab(1),
             0xa ... 0xc /* LF, VT, FF */ => self.screen().newline(),
-            0xd /* CR */  => self.screen().cursor_set(Some(1), None),
+            0xd /* CR */  => self.screen().carriage_return(),
             0xe /* SO */  => self.screen().charset_use(1),
             0xf /* SI */  => self.screen().charset_use(0),
             _ => return None,
@@ -435,6 +1081,11 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
             return Ground;
         }
 
+        if byte == b'c' {
+            self.d.full_reset();
+            return Ground;
+        }
+
         let screen = self.d.screen_mut();
         match byte {
             0x20 ... 0x2f => {
@@ -448,13 +1099,19 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
             b'M' => screen.index(false),
             b'7' => screen.cursor_save(),
             b'8' => screen.cursor_load(),
-            b'c' => screen.reset(),
             b'n' => screen.charset_use(2),
             b'o' => screen.charset_use(3),
+            b'~' => screen.charset_use_gr(1),
+            b'}' => screen.charset_use_gr(2),
+            b'|' => screen.charset_use_gr(3),
+            b'N' => screen.charset_single_shift(2),
+            b'O' => screen.charset_single_shift(3),
 
             b'[' => return CsiEntry,
+            b']' => return OscString,
+            b'P' => return DcsEntry,
             b'_' => return ApcEntry,
-            b'P' | b'X' | b']' | b'^' => return CtrlStrIgnore,
+            b'X' | b'^' => return CtrlStrIgnore,
 
             _ => {
                 // Other sequences ignored either by specification or because we don't implement them
@@ -464,20 +1121,38 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
         Ground
     }
 
-    fn charset_designate(&mut self, slot: u32, param: u8) {
-        if let Some(charset) = VTCharset::decode(param) {
+    /// Designate a charset to the `slot`, `set96` for 96-character sets
+    fn charset_designate(&mut self, slot: u32, set96: bool, param: u8) {
+        let interm = self.p.interm2;
+        let charset = if set96 { VTCharset::decode96(interm, param) } else { VTCharset::decode94(interm, param) };
+        if let Some(charset) = charset {
             self.screen().charset_designate(slot, charset);
         }
     }
 
     fn escape_interm(&mut self, byte: u8) -> State {
-        match (self.p.interm1, byte) {
-            // XXX: Ok to go to ground for 0x20 ~ 0x2f ??? (ditto csi_interm) VT100.net says collect
-            (b'#', b'8') => self.screen().alignment_test(),
-            (b'(', p) => self.charset_designate(0, p),
-            (b')', p) => self.charset_designate(1, p),
-            (b'*', p) => self.charset_designate(2, p),
-            (b'+', p) => self.charset_designate(3, p),
+        // Some charset designators have a second intermediate byte
+        if let 0x20 ... 0x2f = byte {
+            if self.p.interm2 != 0 {
+                return Ground;
+            }
+            self.p.interm2 = byte;
+            return EscapeInterm;
+        }
+
+        match (self.p.interm1, self.p.interm2, byte) {
+            (b'#', 0, b'3') => self.screen().set_line_size(VTLineSize::DoubleTop),
+            (b'#', 0, b'4') => self.screen().set_line_size(VTLineSize::DoubleBottom),
+            (b'#', 0, b'5') => self.screen().set_line_size(VTLineSize::Single),
+            (b'#', 0, b'6') => self.screen().set_line_size(VTLineSize::DoubleWidth),
+            (b'#', 0, b'8') => self.screen().alignment_test(),
+            (b'(', _, p) => self.charset_designate(0, false, p),
+            (b')', _, p) => self.charset_designate(1, false, p),
+            (b'*', _, p) => self.charset_designate(2, false, p),
+            (b'+', _, p) => self.charset_designate(3, false, p),
+            (b'-', _, p) => self.charset_designate(1, true, p),
+            (b'.', _, p) => self.charset_designate(2, true, p),
+            (b'/', _, p) => self.charset_designate(3, true, p),
             _ => {},
         }
 
@@ -486,48 +1161,135 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
 
     fn csi_modes(&mut self, enable: bool) {
         for m in self.p.params.iter() {
-            match *m {
-                4 => self.d.set_mode(VTMode::INSERT, enable),
-                20 => self.d.set_mode(VTMode::NEWLINE, enable),
-                _ => {},
+            if let Some(VTModeKind::Mode(mode)) = VTMode::from_number(false, *m as u32) {
+                self.d.set_mode(mode, enable);
             }
         }
     }
 
     fn csi_modes_dec(&mut self, enable: bool) {
+        for i in 0 .. self.p.params.len() {
+            let mode = self.p.params[i];
+            self.mode_dec(mode, enable);
+        }
+    }
+
+    /// Set or reset a DEC private mode (DECSET / DECRST)
+    fn mode_dec(&mut self, number: i32, enable: bool) {
+        let mode = match VTMode::from_number(true, number as u32) {
+            Some(VTModeKind::Mode(mode)) => mode,
+            _ => VTMode::empty(),
+        };
+
+        match number {
+            6 => {
+                self.d.set_mode(VTMode::ORIGIN, enable);
+                self.d.screen_primary().cursor_set(Some(1), Some(1));
+                self.d.screen_alternate().cursor_set(Some(1), Some(1));
+            },
+            20 => self.d.set_mode(VTMode::NEWLINE, enable),   // FIXME: also applies to input
+            9 | 1000 | 1002 | 1003 => {
+                // Tracking modes replace each other, resetting any of them turns tracking off
+                self.d.set_mode(VTMode::MOUSE_TRACKING, false);
+                if enable {
+                    self.d.set_mode(mode, true);
+                }
+            },
+            1005 | 1006 | 1015 | 1016 => {
+                if enable {
+                    self.d.set_mode(VTMode::MOUSE_ENCODING, false);
+                }
+                self.d.set_mode(mode, enable);
+            },
+            47 | 1047 if  enable => self.d.switch_screen(VTScreenChoice::Alternate),
+            47 | 1047 if !enable => self.d.switch_screen(VTScreenChoice::Primary),
+            1048 if  enable => self.d.screen_mut().cursor_save(),
+            1048 if !enable => self.d.screen_mut().cursor_load(),
+            1049 if  enable => {
+                self.d.screen_primary().cursor_save();
+                self.d.switch_screen(VTScreenChoice::Alternate);
+                // TODO: default style should be set on alt screen (?)
+                self.d.screen_alternate().erase(VTErase::All);
+            },
+            1049 if !enable => {
+                self.d.switch_screen(VTScreenChoice::Primary);
+                self.d.screen_primary().cursor_load();
+            },
+            _ if !mode.is_empty() => self.d.set_mode(mode, enable),
+            _ => {},
+        }
+    }
+
+    /// XTSAVE, save DEC private modes
+    fn csi_modes_save(&mut self) {
         for m in self.p.params.iter() {
-            match *m {
-                1 => self.d.set_mode(VTMode::APP_CURSOR_KEYS, enable),
-                5 => self.d.set_mode(VTMode::REVERSE_VIDEO, enable),
-                6 => {
-                    self.d.set_mode(VTMode::ORIGIN, enable);
-                    self.d.screen_primary().cursor_set(Some(1), Some(1));
-                    self.d.screen_alternate().cursor_set(Some(1), Some(1));
-                },
-                20 => self.d.set_mode(VTMode::NEWLINE, enable),   // FIXME: also applies to input
-                47 | 1047 if  enable => self.d.switch_screen(VTScreenChoice::Primary),
-                47 | 1047 if !enable => self.d.switch_screen(VTScreenChoice::Alternate),
-                1048 if  enable => self.d.screen_mut().cursor_save(),
-                1048 if !enable => self.d.screen_mut().cursor_load(),
-                1049 if  enable => {
-                    self.d.screen_primary().cursor_save();
-                    self.d.switch_screen(VTScreenChoice::Alternate);
-                    // TODO: default style should be set on alt screen (?)
-                    self.d.screen_alternate().erase(VTErase::All);
-                },
-                1049 if !enable => {
-                    self.d.switch_screen(VTScreenChoice::Primary);
-                    self.d.screen_primary().cursor_load();
-                },
-                _ => {},
+            self.d.mode_save(*m as u32);
+        }
+    }
+
+    /// XTRESTORE, restore DEC private modes saved with XTSAVE
+    fn csi_modes_restore(&mut self) {
+        for i in 0 .. self.p.params.len() {
+            let mode = self.p.params[i];
+            if let Some(enable) = self.d.mode_restore(mode as u32) {
+                self.mode_dec(mode, enable);
             }
         }
     }
 
+    /// XTWINOPS, window manipulation and reports.
+    /// Reporting the window title (20, 21) is deliberately not supported as it allows injecting input.
+    fn csi_window_ops(&mut self) {
+        use self::VTWindowRequest::*;
+
+        let params = &self.p.params;
+        let arg = |i| params.get(i, 0) as u32;
+        // Parameter of the title stack operations, 0 means both the icon name and the title
+        let (icon_name, title) = (arg(1) != 2, arg(1) != 1);
+
+        let request = match params.get(0, 0) {
+            1 => Iconify(false),
+            2 => Iconify(true),
+            3 => Move(arg(1), arg(2)),
+            4 => Resize(arg(2), arg(1)),
+            5 => Raise(true),
+            6 => Raise(false),
+            8 => ResizeCells(arg(2), arg(1)),
+            9 => Maximize(arg(1) != 0),
+            10 if arg(1) < 2 => Fullscreen(arg(1) == 1),
+            14 => return self.d.report_request(VTReport::TextAreaPixels),
+            16 => return self.d.report_request(VTReport::CellPixels),
+            18 => return self.d.report_request(VTReport::TextAreaCells),
+            22 => return self.d.title_push(icon_name, title),
+            23 => return self.d.title_pop(icon_name, title),
+            _ => return,
+        };
+
+        self.d.window_request(request);
+    }
+
+    /// DECSCUSR, odd values blink, `0` is the default blinking block
+    fn csi_cursor_style(&mut self) {
+        let (shape, blink) = match self.p.params.get(0, 1) {
+            1 => (VTCursorShape::Block, true),
+            2 => (VTCursorShape::Block, false),
+            3 => (VTCursorShape::Underline, true),
+            4 => (VTCursorShape::Underline, false),
+            5 => (VTCursorShape::Bar, true),
+            6 => (VTCursorShape::Bar, false),
+            _ => return,
+        };
+
+        self.d.set_cursor_shape(shape);
+        self.d.set_mode(VTMode::CURSOR_BLINK, blink);
+    }
+
     /// Character rendition setting. The one escape sequence people actually know to exist.
     fn csi_sgr(&mut self) {
         let set_default = |screen: &mut D::Screen| {
             screen.set_rendition(VTRendition::ALL, false);
+            screen.set_underline(VTUnderline::None);
+            screen.set_underline_color(None);
             screen.set_fg(VTColor::DefaultFg);
             screen.set_bg(VTColor::DefaultBg);
         };
@@ -538,25 +1300,38 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
         }
 
         let screen = &mut self.d.screen_mut();
-        let mut it = self.p.params.iter().map(|p| *p);
-        while let Some(p) = it.next() {
-            match p {
+        let mut it = self.p.params.groups();
+        while let Some(group) = it.next() {
+            match group[0] {
                 0 => set_default(screen),
                 1 => screen.set_rendition(VTRendition::BOLD, true),
-                4 => screen.set_rendition(VTRendition::UNDERLINED, true),
+                2 => screen.set_rendition(VTRendition::DIM, true),
+                3 => screen.set_rendition(VTRendition::ITALIC, true),
+                4 => match group.get(1).map(|p| VTUnderline::from_param(*p)) {
+                    None => screen.set_underline(VTUnderline::Single),
+                    Some(Some(underline)) => screen.set_underline(underline),
+                    Some(None) => return,
+                },
                 5 => screen.set_rendition(VTRendition::BLINKING, true),
+                6 => screen.set_rendition(VTRendition::RAPID_BLINK, true),
                 7 => screen.set_rendition(VTRendition::INVERSE, true),
                 8 => screen.set_rendition(VTRendition::INVISIBLE, true),
-
-                22 => screen.set_rendition(VTRendition::BOLD, false),
-                24 => screen.set_rendition(VTRendition::UNDERLINED, false),
-                25 => screen.set_rendition(VTRendition::BLINKING, false),
+                9 => screen.set_rendition(VTRendition::STRIKE, true),
+                21 => screen.set_underline(VTUnderline::Double),
+                53 => screen.set_rendition(VTRendition::OVERLINE, true),
+
+                22 => screen.set_rendition(VTRendition::BOLD | VTRendition::DIM, false),
+                23 => screen.set_rendition(VTRendition::ITALIC, false),
+                24 => screen.set_underline(VTUnderline::None),
+                25 => screen.set_rendition(VTRendition::BLINKING | VTRendition::RAPID_BLINK, false),
                 27 => screen.set_rendition(VTRendition::INVERSE, false),
                 28 => screen.set_rendition(VTRendition::INVISIBLE, false),
+                29 => screen.set_rendition(VTRendition::STRIKE, false),
+                55 => screen.set_rendition(VTRendition::OVERLINE, false),
 
                 // Foreground color
                 30 ... 39 | 90 ... 97 => {
-                    match VTColor::parse(p, &mut it) {
+                    match VTColor::parse(group, &mut it) {
                         Ok(c) => screen.set_fg(c),
                         Err(_) => return,
                     }
@@ -564,12 +1339,21 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
 
                 // Background color
                 40 ... 49 | 100 ... 107 => {
-                    match VTColor::parse(p, &mut it) {
+                    match VTColor::parse(group, &mut it) {
                         Ok(c) => screen.set_bg(c),
                         Err(_) => return,
                     }
                 },
 
+                // Underline color
+                58 => {
+                    match VTColor::parse(group, &mut it) {
+                        Ok(c) => screen.set_underline_color(Some(c)),
+                        Err(_) => return,
+                    }
+                },
+                59 => screen.set_underline_color(None),
+
                 _ => {
                     // What's the right thing to do here?
                     // I'd say just bail to avoid interpreting params that might be bogus ...
@@ -616,22 +1400,37 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
     }
 
     fn csi_dispatch(&mut self, byte: u8) -> State {
+        // Subparameters are only used by SGR, like xterm we ignore other sequences containing them
+        if self.p.params.has_subs() && (byte != b'm' || self.p.interm1 != 0 || self.p.interm2 != 0) {
+            return Ground;
+        }
+
         if self.p.interm1 != 0 {
             match (self.p.interm1, byte) {
                 (b'>', b'c') => self.d.report_request(VTReport::SecondaryAttrs),
                 (b'?', b'h') => self.csi_modes_dec(true),
                 (b'?', b'l') => self.csi_modes_dec(false),
-
-                // XXX: support these? xterm specific?
-                // case csi("?r"): /* restore mode */ break;
-                // case csi("?s"): /* save mode */ break;
+                (b'?', b'p') if self.p.interm2 == b'$' => {
+                    let number = self.p.params.get(0, 0) as u32;
+                    self.d.report_request(VTReport::Mode { private: true, number });
+                },
+                (b'?', b's') if self.p.interm2 == 0 => self.csi_modes_save(),
+                (b'?', b'r') if self.p.interm2 == 0 => self.csi_modes_restore(),
                 _ => {},
             }
             return Ground;
         }
 
         if self.p.interm2 != 0 {
-            // We support no such CSIs
+            match (self.p.interm2, byte) {
+                (b' ', b'q') => self.csi_cursor_style(),
+                (b'!', b'p') => self.d.soft_reset(),
+                (b'$', b'p') => {
+                    let number = self.p.params.get(0, 0) as u32;
+                    self.d.report_request(VTReport::Mode { private: false, number });
+                },
+                _ => {},
+            }
             return Ground;
         }
 
@@ -643,6 +1442,7 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
             b'l' => self.csi_modes(false),
             b'm' => self.csi_sgr(),
             b'n' => self.csi_dsr(),
+            b't' => self.csi_window_ops(),
             b'x' => self.csi_tparm(),
             _ => {
                 // The saga continues in the following match.
@@ -656,11 +1456,18 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
             return Ground;
         }
 
+        // DECSLRM takes over SCOSC while left and right margins are enabled
+        if byte == b's' && self.d.mode().contains(VTMode::LR_MARGINS) {
+            let (left, right) = (self.p.params.get(0, 0) as u32, self.p.params.get(1, 0) as u32);
+            self.d.screen_mut().set_margins(left, right);
+            return Ground;
+        }
+
         let screen = self.d.screen_mut();
         let params = &self.p.params;
 
         match byte {
-            b'@' => screen.put_chars(params.get(0, 1) as u32),
+            b'@' => screen.insert_chars(params.get(0, 1) as u32),
             b'A' => screen.cursor_move(0, -params.get(0, 1)),
             b'B' => screen.cursor_move(0,  params.get(0, 1)),
             b'C' => screen.cursor_move( params.get(0, 1), 0),
@@ -670,11 +1477,12 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
             b'I' => screen.tab(params.get(0, 1)),
             b'L' => screen.scroll_at_cursor(-params.get(0, 1)),
             b'M' => screen.scroll_at_cursor(params.get(0, 1)),
-            b'P' => screen.erase(VTErase::NumChars(params.get(0, 1) as u32)),   // TODO: is this right?
+            b'P' => screen.delete_chars(params.get(0, 1) as u32),
             b'S' => screen.scroll( params.get(0, 1)),
             b'T' => screen.scroll(-params.get(0, 1)),
             b'X' => screen.erase(VTErase::NumChars(params.get(0, 1) as u32)),
             b'Z' => screen.tab(-params.get(0, 1)),
+            b'b' => screen.repeat_char(params.get(0, 1) as u32),
             b'd' => screen.cursor_set(None, Some(params.get(0, 1) as u32)),
             b'r' => screen.set_scroll_region(params.get(0, 0) as u32, params.get(1, 0) as u32),
             b's' => screen.cursor_save(),
@@ -704,10 +1512,13 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
                 CsiParam
             },
             b';' => {
-                self.p.params.next();
+                self.p.params.next(false);
+                CsiParam
+            },
+            b':' => {
+                self.p.params.next(true);
                 CsiParam
             },
-            b':' => CsiIgnore,
             0x40 ... 0x7e => self.csi_dispatch(byte),
             _ => Ground,
         }
@@ -724,10 +1535,14 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
                 CsiParam
             },
             b';' => {
-                self.p.params.next();
+                self.p.params.next(false);
+                CsiParam
+            },
+            b':' => {
+                self.p.params.next(true);
                 CsiParam
             },
-            b':' | b'<' | b'=' | b'>' | b'?' => CsiIgnore,
+            b'<' | b'=' | b'>' | b'?' => CsiIgnore,
             0x40 ... 0x7e => {
                 self.csi_dispatch(byte);
                 Ground
@@ -755,11 +1570,217 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
         }
     }
 
+    /// Dispatches the control string collected so far, if any.
+    /// Called when a control string is terminated by ST (or BEL in case of OSC).
+    fn ctrl_str_end(&mut self) {
+        match self.p.state {
+            OscString => self.osc_dispatch(),
+            DcsPassthrough => self.d.dcs_unhook(true),
+            ApcTp => self.apc_tp_dispatch(),
+            ApcGraphics => self.d.kitty_graphics(&self.p.data),
+            _ => {},
+        }
+    }
+
+    fn osc_string(&mut self, byte: u8) -> State {
+        match byte {
+            // C0 bytes are ignored in OSC (terminators are handled in `check_anywhere()`)
+            0 ... 0x1f => {},
+            _ if self.p.data.len() < STRING_MAX => self.p.data.push(byte),
+            _ => {},
+        }
+
+        OscString
+    }
+
+    /// Clipboard manipulation, the argument format is `Pc ; Pd` where `Pc` is a list of selections
+    /// and `Pd` is either base64-encoded data to store or `?` to query the selection.
+    fn osc_clipboard(&mut self, arg: &str) {
+        let mut split = arg.splitn(2, ';');
+        let targets = split.next().unwrap_or("");
+        let data = match split.next() {
+            Some(data) => data,
+            None => return,
+        };
+
+        let mut selections: Vec<VTSelection> = vec![];
+        for selection in targets.bytes().filter_map(VTSelection::decode) {
+            if !selections.contains(&selection) {
+                selections.push(selection);
+            }
+        }
+        if targets.is_empty() {
+            selections.push(VTSelection::Clipboard);
+        }
+
+        if data == "?" {
+            for selection in selections {
+                self.d.clipboard_load(selection);
+            }
+        } else {
+            // Invalid data clears the selection
+            let data = base64::decode(data.as_bytes()).unwrap_or_default();
+            let data = String::from_utf8_lossy(&data);
+            for selection in selections {
+                self.d.clipboard_store(selection, &data);
+            }
+        }
+    }
+
+    fn osc_palette_color(&mut self, slot: VTPaletteSlot, spec: &str) {
+        if spec == "?" {
+            self.d.palette_query(slot);
+        } else if let Some(rgb) = parse_color_spec(spec) {
+            self.d.palette_set(slot, rgb);
+        }
+    }
+
+    /// Indexed palette colors, the argument format is a list of `c ; spec` pairs
+    fn osc_palette(&mut self, arg: &str) {
+        let mut it = arg.split(';');
+        while let (Some(index), Some(spec)) = (it.next(), it.next()) {
+            match index.parse::<u8>() {
+                Ok(index) => self.osc_palette_color(VTPaletteSlot::Indexed(index), spec),
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Dynamic colors, each extra argument applies to the following dynamic color,
+    /// eg. `OSC 10 ; fg ; bg` sets both foreground and background.
+    fn osc_palette_dynamic(&mut self, cmd: u32, arg: &str) {
+        for (cmd, spec) in (cmd ..).zip(arg.split(';')) {
+            match VTPaletteSlot::dynamic(cmd) {
+                Some(slot) => self.osc_palette_color(slot, spec),
+                None => return,
+            }
+        }
+    }
+
+    fn osc_palette_reset(&mut self, arg: &str) {
+        if arg.is_empty() {
+            self.d.palette_reset(None);
+            return;
+        }
+
+        for index in arg.split(';').filter_map(|index| index.parse::<u8>().ok()) {
+            self.d.palette_reset(Some(VTPaletteSlot::Indexed(index)));
+        }
+    }
+
+    /// Operating System Command. The payload format is `Ps ; Pt` where `Ps` is a numeric command.
+    fn osc_dispatch(&mut self) {
+        let data = String::from_utf8_lossy(&self.p.data).into_owned();
+        let (cmd, arg) = match data.find(';') {
+            Some(i) => (&data[..i], &data[i + 1 ..]),
+            None => (&data[..], ""),
+        };
+
+        let cmd = match cmd.parse::<u32>() {
+            Ok(cmd) => cmd,
+            Err(_) => return,
+        };
+
+        match cmd {
+            0 => {
+                self.d.set_icon_name(arg);
+                self.d.set_title(arg);
+            },
+            1 => self.d.set_icon_name(arg),
+            2 => self.d.set_title(arg),
+            4 => self.osc_palette(arg),
+            7 => {
+                if let Some((host, path)) = parse_file_uri(arg) {
+                    self.d.set_working_dir(host, &path);
+                }
+            },
+            8 => self.screen().set_hyperlink(VTHyperlink::parse(arg)),
+            10 ... 12 => self.osc_palette_dynamic(cmd, arg),
+            52 => self.osc_clipboard(arg),
+            104 => self.osc_palette_reset(arg),
+            133 => {
+                if let Some(mark) = VTSemanticMark::parse(arg) {
+                    self.screen().semantic_mark(mark);
+                }
+            },
+            110 ... 112 => self.d.palette_reset(VTPaletteSlot::dynamic(cmd - 100)),
+            _ => {
+                // Other OSCs are not supported
+            },
+        }
+    }
+
+    fn dcs_hook(&mut self, byte: u8) -> State {
+        self.d.dcs_hook(self.p.interm1, self.p.interm2, &self.p.params, byte);
+        DcsPassthrough
+    }
+
+    fn dcs_entry(&mut self, byte: u8) -> State {
+        match byte {
+            0x20 ... 0x2f => {
+                self.p.interm2 = byte;
+                DcsInterm
+            },
+            b'<' | b'=' | b'>' | b'?' => {
+                self.p.interm1 = byte;
+                DcsParam
+            },
+            b'0' ... b'9' => {
+                self.p.params.push_digit(byte - b'0');
+                DcsParam
+            },
+            b';' => {
+                self.p.params.next(false);
+                DcsParam
+            },
+            b':' => DcsIgnore,
+            0x40 ... 0x7e => self.dcs_hook(byte),
+            _ => DcsEntry,
+        }
+    }
+
+    fn dcs_param(&mut self, byte: u8) -> State {
+        match byte {
+            0x20 ... 0x2f => {
+                self.p.interm2 = byte;
+                DcsInterm
+            },
+            b'0' ... b'9' => {
+                self.p.params.push_digit(byte - b'0');
+                DcsParam
+            },
+            b';' => {
+                self.p.params.next(false);
+                DcsParam
+            },
+            b':' | b'<' | b'=' | b'>' | b'?' => DcsIgnore,
+            0x40 ... 0x7e => self.dcs_hook(byte),
+            _ => DcsParam,
+        }
+    }
+
+    fn dcs_interm(&mut self, byte: u8) -> State {
+        match byte {
+            // Only one intermediate byte is supported, like with CSI
+            0x20 ... 0x3f => DcsIgnore,
+            0x40 ... 0x7e => self.dcs_hook(byte),
+            _ => DcsInterm,
+        }
+    }
+
+    fn dcs_passthrough(&mut self, byte: u8) -> State {
+        if byte != 0x7f {
+            self.d.dcs_put(byte);
+        }
+        DcsPassthrough
+    }
+
     fn apc_entry(&mut self, byte: u8) -> State {
         self.clear();   // Legal, because we'll transition to another state
 
         match byte {
             b'T' => ApcInterm,
+            b'G' => ApcGraphics,
             _ => CtrlStrIgnore,
         }
     }
@@ -771,9 +1792,42 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
         }
     }
 
-    fn apc_tp(&mut self, byte: u8) -> State {
-        // TODO
-        ApcTp
+    /// Collects APC data, used for both `ApcTp` and `ApcGraphics`
+    fn apc_string(&mut self, byte: u8) -> State {
+        match byte {
+            0 ... 0x1f => {},
+            _ if self.p.data.len() < STRING_MAX => self.p.data.push(byte),
+            _ => {},
+        }
+
+        self.p.state
+    }
+
+    /// Teepee-private APC: `ESC _ T P <command> ( ; <key> = <value> )* ST`.
+    /// Values are percent-encoded, at least `%` and `;` need to be. Unknown commands and keys are ignored.
+    ///  - `SetUserVar ; name=<name> [; value=<value>]`: Set a per-session user variable, unset it if there's no value
+    ///  - `Bookmark [; label=<label>]`: Bookmark the cursor line
+    ///  - `NewTab [; cwd=<path>]`: Ask the application to open a new tab, optionally in the given directory
+    fn apc_tp_dispatch(&mut self) {
+        let data = String::from_utf8_lossy(&self.p.data).into_owned();
+        let mut fields = data.split(';');
+        let cmd = fields.next().unwrap_or("");
+        let args: Vec<(&str, Vec<u8>)> = fields
+            .filter_map(|field| field.find('=').map(|i| (&field[..i], percent_decode(&field[i + 1 ..]))))
+            .collect();
+        let arg = |key: &str| args.iter().find(|arg| arg.0 == key).map(|arg| &arg.1[..]);
+
+        match cmd {
+            "SetUserVar" => {
+                if let Some(name) = arg("name") {
+                    let value = arg("value").map(String::from_utf8_lossy);
+                    self.d.tp_set_user_var(&String::from_utf8_lossy(name), value.as_deref());
+                }
+            },
+            "Bookmark" => self.d.tp_bookmark(&String::from_utf8_lossy(arg("label").unwrap_or(b""))),
+            "NewTab" => self.d.tp_new_tab(arg("cwd").map(|cwd| Path::new(OsStr::from_bytes(cwd)))),
+            _ => {},
+        }
     }
 
     fn ctrl_str_ignore(&mut self, _byte: u8) -> State {
@@ -796,9 +1850,16 @@ impl<'s, 'd, D: VTDispatch + 'static> Dispatcher<'s, 'd, D> {
             CsiParam      => Self::csi_param,
             CsiInterm     => Self::csi_interm,
             CsiIgnore     => Self::csi_ignore,
+            OscString     => Self::osc_string,
+            DcsEntry      => Self::dcs_entry,
+            DcsParam      => Self::dcs_param,
+            DcsInterm     => Self::dcs_interm,
+            DcsPassthrough => Self::dcs_passthrough,
+            DcsIgnore     => Self::ctrl_str_ignore,
             ApcEntry      => Self::apc_entry,
             ApcInterm     => Self::apc_interm,
-            ApcTp         => Self::apc_tp,
+            ApcTp         => Self::apc_string,
+            ApcGraphics   => Self::apc_string,
             CtrlStrIgnore => Self::ctrl_str_ignore,
         } (self, byte);
     }
@@ -813,6 +1874,7 @@ impl VTParser {
             interm1: 0,
             interm2: 0,
             params: Params::new(),
+            data: vec![],
         }
     }
 
@@ -821,6 +1883,7 @@ impl VTParser {
         self.params.clear();
         self.interm1 = 0;
         self.interm2 = 0;
+        self.data.clear();
         self.utf8.reset()
     }
 
@@ -852,6 +1915,7 @@ mod tests {
     #[derive(Debug, Default)]
     struct TestDispatch {
         calls: Vec<Call>,
+        mode: VTMode,
     }
 
     impl TestDispatch {
@@ -869,8 +1933,11 @@ mod tests {
 
     impl VTScreen for TestDispatch {
         dispatch_impl!(put_char, ch: char);
-        dispatch_impl!(put_chars, num: u32);
+        dispatch_impl!(insert_chars, num: u32);
+        dispatch_impl!(delete_chars, num: u32);
+        dispatch_impl!(repeat_char, num: u32);
         dispatch_impl!(newline);
+        dispatch_impl!(carriage_return);
         dispatch_impl!(index, forward: bool);
         dispatch_impl!(next_line);
         dispatch_impl!(erase, erase: VTErase);
@@ -881,11 +1948,18 @@ mod tests {
         dispatch_impl!(scroll, num: i32);
         dispatch_impl!(scroll_at_cursor, num: i32);
         dispatch_impl!(set_scroll_region, top: u32, bottom: u32);
+        dispatch_impl!(set_margins, left: u32, right: u32);
         dispatch_impl!(set_mode, mode: VTMode, enable: bool);
         dispatch_impl!(set_rendition, rend: VTRendition, enable: bool);
+        dispatch_impl!(set_underline, underline: VTUnderline);
+        dispatch_impl!(set_underline_color, color: Option<VTColor>);
         dispatch_impl!(set_fg, color: VTColor);
         dispatch_impl!(set_bg, color: VTColor);
+        dispatch_impl!(set_hyperlink, link: Option<VTHyperlink>);
+        dispatch_impl!(semantic_mark, mark: VTSemanticMark);
         dispatch_impl!(charset_use, slot: u32);
+        dispatch_impl!(charset_use_gr, slot: u32);
+        dispatch_impl!(charset_single_shift, slot: u32);
         dispatch_impl!(charset_designate, slot: u32, charset: VTCharset);
         fn cursor(&self) -> (u32, u32) { (1, 1) }
         dispatch_impl!(cursor_set, x: Option<u32>, y: Option<u32>);
@@ -893,6 +1967,7 @@ mod tests {
         dispatch_impl!(cursor_save);
         dispatch_impl!(cursor_load);
         dispatch_impl!(alignment_test);
+        dispatch_impl!(set_line_size, size: VTLineSize);
     }
 
     impl VTDispatch for TestDispatch {
@@ -903,10 +1978,43 @@ mod tests {
         fn screen_primary(&mut self) -> &mut Self::Screen { self }
         fn screen_alternate(&mut self) -> &mut Self::Screen { self }
 
+        fn mode(&self) -> VTMode { self.mode }
+
+        fn set_mode(&mut self, mode: VTMode, enable: bool) {
+            self.mode.set(mode, enable);
+            self.calls.push(call!(set_mode, mode, enable));
+        }
+
         dispatch_impl!(switch_screen, screen: VTScreenChoice);
-        dispatch_impl!(set_mode, mode: VTMode, enable: bool);
+        dispatch_impl!(full_reset);
+        dispatch_impl!(soft_reset);
+        dispatch_impl!(mode_save, number: u32);
+        // Pretends every mode was saved as set
+        fn mode_restore(&mut self, number: u32) -> Option<bool> {
+            self.calls.push(call!(mode_restore, number));
+            Some(true)
+        }
+        dispatch_impl!(set_cursor_shape, shape: VTCursorShape);
         dispatch_impl!(report_request, report: VTReport);
         dispatch_impl!(bell);
+        dispatch_impl!(set_title, title: &str);
+        dispatch_impl!(set_icon_name, name: &str);
+        dispatch_impl!(title_push, icon_name: bool, title: bool);
+        dispatch_impl!(title_pop, icon_name: bool, title: bool);
+        dispatch_impl!(window_request, request: VTWindowRequest);
+        dispatch_impl!(set_working_dir, host: &str, path: &Path);
+        dispatch_impl!(clipboard_store, selection: VTSelection, data: &str);
+        dispatch_impl!(clipboard_load, selection: VTSelection);
+        dispatch_impl!(palette_set, slot: VTPaletteSlot, rgb: (u8, u8, u8));
+        dispatch_impl!(palette_reset, slot: Option<VTPaletteSlot>);
+        dispatch_impl!(palette_query, slot: VTPaletteSlot);
+        dispatch_impl!(dcs_hook, private: u8, interm: u8, params: &[i32], action: u8);
+        dispatch_impl!(dcs_put, byte: u8);
+        dispatch_impl!(dcs_unhook, complete: bool);
+        dispatch_impl!(kitty_graphics, data: &[u8]);
+        dispatch_impl!(tp_set_user_var, name: &str, value: Option<&str>);
+        dispatch_impl!(tp_bookmark, label: &str);
+        dispatch_impl!(tp_new_tab, working_dir: Option<&Path>);
     }
 
     macro_rules! parse {
@@ -930,6 +2038,20 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn line_editing() {
+        assert_eq!(parse!(b"\x1b[@\x1b[3@\x1b[P\x1b[2P\x1b[X\x1b[4X\x1b[b\x1b[5b"), vec![
+            call!(insert_chars, 1),
+            call!(insert_chars, 3),
+            call!(delete_chars, 1),
+            call!(delete_chars, 2),
+            call!(erase, VTErase::NumChars(1)),
+            call!(erase, VTErase::NumChars(4)),
+            call!(repeat_char, 1),
+            call!(repeat_char, 5),
+        ]);
+    }
+
     #[test]
     fn cancelations() {
         assert_eq!(parse!(b"\x1b[1;30\x18\x1b[34m"), vec![ call!(set_fg, VTColor::Indexed(4)) ]);
@@ -945,10 +2067,346 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn sgr_colors() {
+        assert_eq!(parse!(b"\x1b[38;5;100;48;2;1;2;3m"), vec![
+            call!(set_fg, VTColor::Indexed(100)),
+            call!(set_bg, VTColor::Rgb(1, 2, 3)),
+        ]);
+        assert_eq!(parse!(b"\x1b[38:5:100;48:2::1:2:3;1m"), vec![
+            call!(set_fg, VTColor::Indexed(100)),
+            call!(set_bg, VTColor::Rgb(1, 2, 3)),
+            call!(set_rendition, VTRendition::BOLD, true),
+        ]);
+        assert_eq!(parse!(b"\x1b[38:2:1:2:3m"), vec![ call!(set_fg, VTColor::Rgb(1, 2, 3)) ]);
+        assert_eq!(parse!(b"\x1b[38:2:0:1:2:3:0:0m"), vec![ call!(set_fg, VTColor::Rgb(1, 2, 3)) ]);
+        assert_eq!(parse!(b"\x1b[38:5:256;1m"), vec![]);
+    }
+
+    #[test]
+    fn sgr_underline_color() {
+        assert_eq!(parse!(b"\x1b[58;5;1m\x1b[58:2::1:2:3m\x1b[58;2;1;2;3m\x1b[58:5:9m\x1b[59m"), vec![
+            call!(set_underline_color, Some(VTColor::Indexed(1))),
+            call!(set_underline_color, Some(VTColor::Rgb(1, 2, 3))),
+            call!(set_underline_color, Some(VTColor::Rgb(1, 2, 3))),
+            call!(set_underline_color, Some(VTColor::Indexed(9))),
+            call!(set_underline_color, None::<VTColor>),
+        ]);
+        assert_eq!(parse!(b"\x1b[58;5m"), vec![]);
+    }
+
+    #[test]
+    fn sgr_rendition() {
+        assert_eq!(parse!(b"\x1b[2;3;6;9;21;53m"), vec![
+            call!(set_rendition, VTRendition::DIM, true),
+            call!(set_rendition, VTRendition::ITALIC, true),
+            call!(set_rendition, VTRendition::RAPID_BLINK, true),
+            call!(set_rendition, VTRendition::STRIKE, true),
+            call!(set_underline, VTUnderline::Double),
+            call!(set_rendition, VTRendition::OVERLINE, true),
+        ]);
+        assert_eq!(parse!(b"\x1b[22;23;25;29;55m"), vec![
+            call!(set_rendition, VTRendition::BOLD | VTRendition::DIM, false),
+            call!(set_rendition, VTRendition::ITALIC, false),
+            call!(set_rendition, VTRendition::BLINKING | VTRendition::RAPID_BLINK, false),
+            call!(set_rendition, VTRendition::STRIKE, false),
+            call!(set_rendition, VTRendition::OVERLINE, false),
+        ]);
+    }
+
+    #[test]
+    fn subparams_non_sgr() {
+        assert_eq!(parse!(b"\x1b[1:2H\x1b[?1000:1h\x1b[1:H\x1b[2;3H"), vec![
+            call!(cursor_set, Some(3), Some(2)),
+        ]);
+    }
+
+    #[test]
+    fn sgr_underline() {
+        assert_eq!(parse!(b"\x1b[4;4:3;4:0;24m"), vec![
+            call!(set_underline, VTUnderline::Single),
+            call!(set_underline, VTUnderline::Curly),
+            call!(set_underline, VTUnderline::None),
+            call!(set_underline, VTUnderline::None),
+        ]);
+        assert_eq!(parse!(b"\x1b[4:2m\x1b[4:4m\x1b[4:5m"), vec![
+            call!(set_underline, VTUnderline::Double),
+            call!(set_underline, VTUnderline::Dotted),
+            call!(set_underline, VTUnderline::Dashed),
+        ]);
+        assert_eq!(parse!(b"\x1b[;1m"), vec![
+            call!(set_rendition, VTRendition::ALL, false),
+            call!(set_underline, VTUnderline::None),
+            call!(set_underline_color, None::<VTColor>),
+            call!(set_fg, VTColor::DefaultFg),
+            call!(set_bg, VTColor::DefaultBg),
+            call!(set_rendition, VTRendition::BOLD, true),
+        ]);
+    }
+
+    #[test]
+    fn cursor_style() {
+        assert_eq!(parse!(b"\x1b[6 q\x1b[ q\x1b[7 q\x1b[?25;12l"), vec![
+            call!(set_cursor_shape, VTCursorShape::Bar),
+            call!(set_mode, VTMode::CURSOR_BLINK, false),
+            call!(set_cursor_shape, VTCursorShape::Block),
+            call!(set_mode, VTMode::CURSOR_BLINK, true),
+            call!(set_mode, VTMode::CURSOR_VISIBLE, false),
+            call!(set_mode, VTMode::CURSOR_BLINK, false),
+        ]);
+    }
+
+    #[test]
+    fn window_ops() {
+        use self::VTWindowRequest::*;
+        assert_eq!(parse!(b"\x1b[2t\x1b[8;24;80t\x1b[4;;640t\x1b[9;1t\x1b[10;2t\x1b[21t"), vec![
+            call!(window_request, Iconify(true)),
+            call!(window_request, ResizeCells(80, 24)),
+            call!(window_request, Resize(640, 0)),
+            call!(window_request, Maximize(true)),
+        ]);
+        assert_eq!(parse!(b"\x1b[14t\x1b[16t\x1b[18t"), vec![
+            call!(report_request, VTReport::TextAreaPixels),
+            call!(report_request, VTReport::CellPixels),
+            call!(report_request, VTReport::TextAreaCells),
+        ]);
+        assert_eq!(parse!(b"\x1b[22;0t\x1b[22;1t\x1b[23;2t"), vec![
+            call!(title_push, true, true),
+            call!(title_push, true, false),
+            call!(title_pop, false, true),
+        ]);
+    }
+
+    #[test]
+    fn decrqm() {
+        assert_eq!(parse!(b"\x1b[?2026$p\x1b[4$p\x1b[?2026p"), vec![
+            call!(report_request, VTReport::Mode { private: true, number: 2026 }),
+            call!(report_request, VTReport::Mode { private: false, number: 4 }),
+        ]);
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(parse!(b"\x1b[4;12;3h\x1b[?7;3;1047l"), vec![
+            call!(set_mode, VTMode::INSERT, true),
+            call!(set_mode, VTMode::WRAP, false),
+            call!(switch_screen, VTScreenChoice::Primary),
+        ]);
+        assert_eq!(parse!(b"\x1b[?2004;1000s\x1b[?1000r"), vec![
+            call!(mode_save, 2004),
+            call!(mode_save, 1000),
+            call!(mode_restore, 1000),
+            call!(set_mode, VTMode::MOUSE_TRACKING, false),
+            call!(set_mode, VTMode::MOUSE_NORMAL, true),
+        ]);
+    }
+
+    #[test]
+    fn mouse_modes() {
+        assert_eq!(parse!(b"\x1b[?1002;1006h\x1b[?1000l"), vec![
+            call!(set_mode, VTMode::MOUSE_TRACKING, false),
+            call!(set_mode, VTMode::MOUSE_BUTTON, true),
+            call!(set_mode, VTMode::MOUSE_ENCODING, false),
+            call!(set_mode, VTMode::MOUSE_SGR, true),
+            call!(set_mode, VTMode::MOUSE_TRACKING, false),
+        ]);
+        assert_eq!(parse!(b"\x1b[?1015l"), vec![ call!(set_mode, VTMode::MOUSE_URXVT, false) ]);
+    }
+
+    #[test]
+    fn reset() {
+        assert_eq!(parse!(b"\x1bc\x1b[!p\x1b[p"), vec![ call!(full_reset), call!(soft_reset) ]);
+    }
+
+    #[test]
+    fn margins() {
+        assert_eq!(parse!(b"\x1b[s\x1b[?69h\x1b[2;5s\x1b[s\x1b[?69l\x1b[s"), vec![
+            call!(cursor_save),
+            call!(set_mode, VTMode::LR_MARGINS, true),
+            call!(set_margins, 2, 5),
+            call!(set_margins, 0, 0),
+            call!(set_mode, VTMode::LR_MARGINS, false),
+            call!(cursor_save),
+        ]);
+    }
+
     #[test]
     fn alignment_test() {
         assert_eq!(parse!(b"\x1b#8"), vec![ call!(alignment_test) ]);
     }
 
+    #[test]
+    fn charsets() {
+        assert_eq!(parse!(b"\x1b(0\x1b)A\x1b*%5\x1b+>\x1b(K\x1b)%6\x1b-A\x1b(%9\x1b(!AB"), vec![
+            call!(charset_designate, 0, VTCharset::Graphics),
+            call!(charset_designate, 1, VTCharset::Uk),
+            call!(charset_designate, 2, VTCharset::DecSupplemental),
+            call!(charset_designate, 3, VTCharset::DecTechnical),
+            call!(charset_designate, 0, VTCharset::Nrcs(VTNrcs::German)),
+            call!(charset_designate, 1, VTCharset::Nrcs(VTNrcs::Portuguese)),
+            call!(charset_designate, 1, VTCharset::Latin1),
+            call!(put_char, 'B'),
+        ]);
+        assert_eq!(parse!(b"\x0e\x0f\x1bn\x1bo\x1b~\x1b}\x1b|\x1bN\x1bO"), vec![
+            call!(charset_use, 1),
+            call!(charset_use, 0),
+            call!(charset_use, 2),
+            call!(charset_use, 3),
+            call!(charset_use_gr, 1),
+            call!(charset_use_gr, 2),
+            call!(charset_use_gr, 3),
+            call!(charset_single_shift, 2),
+            call!(charset_single_shift, 3),
+        ]);
+    }
+
+    #[test]
+    fn line_size() {
+        assert_eq!(parse!(b"\x1b#3\x1b#4\x1b#5\x1b#6"), vec![
+            call!(set_line_size, VTLineSize::DoubleTop),
+            call!(set_line_size, VTLineSize::DoubleBottom),
+            call!(set_line_size, VTLineSize::Single),
+            call!(set_line_size, VTLineSize::DoubleWidth),
+        ]);
+    }
+
+    #[test]
+    fn osc_title() {
+        assert_eq!(parse!(b"\x1b]2;Hello\x1b\\"), vec![ call!(set_title, "Hello") ]);
+        assert_eq!(parse!(b"\x1b]1;icon\x07"), vec![ call!(set_icon_name, "icon") ]);
+        assert_eq!(parse!(b"\x1b]0;both; and more\x07x"), vec![
+            call!(set_icon_name, "both; and more"),
+            call!(set_title, "both; and more"),
+            call!(put_char, 'x'),
+        ]);
+    }
+
+    #[test]
+    fn osc_cancel() {
+        assert_eq!(parse!(b"\x1b]2;Hello\x18x"), vec![ call!(put_char, 'x') ]);
+        assert_eq!(parse!(b"\x1b]foo\x07"), vec![]);
+    }
+
+    #[test]
+    fn osc_hyperlink() {
+        let link = VTHyperlink { id: Some("foo".into()), uri: "file:///etc/fstab".into() };
+        assert_eq!(parse!(b"\x1b]8;x=y:id=foo;file:///etc/fstab\x1b\\a\x1b]8;;\x1b\\"), vec![
+            call!(set_hyperlink, Some(link)),
+            call!(put_char, 'a'),
+            call!(set_hyperlink, None::<VTHyperlink>),
+        ]);
+
+        let link = VTHyperlink { id: None, uri: "http://example.com/a;b".into() };
+        assert_eq!(parse!(b"\x1b]8;;http://example.com/a;b\x07"), vec![ call!(set_hyperlink, Some(link)) ]);
+
+        // Overlong URIs end the hyperlink, overlong ids are dropped
+        let long = "x".repeat(HYPERLINK_URI_MAX + 1);
+        assert_eq!(parse!(format!("\x1b]8;;{}\x07", long).as_bytes()), vec![ call!(set_hyperlink, None::<VTHyperlink>) ]);
+        let link = VTHyperlink { id: None, uri: "http://example.com".into() };
+        assert_eq!(parse!(format!("\x1b]8;id={};http://example.com\x07", &long[..HYPERLINK_ID_MAX + 1]).as_bytes()), vec![
+            call!(set_hyperlink, Some(link)),
+        ]);
+    }
+
+    #[test]
+    fn osc_clipboard() {
+        assert_eq!(parse!(b"\x1b]52;c;Zm9v\x07"), vec![ call!(clipboard_store, VTSelection::Clipboard, "foo") ]);
+        assert_eq!(parse!(b"\x1b]52;;Zm9v\x07"), vec![ call!(clipboard_store, VTSelection::Clipboard, "foo") ]);
+        assert_eq!(parse!(b"\x1b]52;cp;?\x1b\\"), vec![
+            call!(clipboard_load, VTSelection::Clipboard),
+            call!(clipboard_load, VTSelection::Primary),
+        ]);
+        assert_eq!(parse!(b"\x1b]52;pcsc;?\x07"), vec![
+            call!(clipboard_load, VTSelection::Primary),
+            call!(clipboard_load, VTSelection::Clipboard),
+        ]);
+        assert_eq!(parse!(b"\x1b]52;p;!!\x07"), vec![ call!(clipboard_store, VTSelection::Primary, "") ]);
+        assert_eq!(parse!(b"\x1b]52;c\x07"), vec![]);
+    }
+
+    #[test]
+    fn osc_palette() {
+        use self::VTPaletteSlot::*;
+
+        assert_eq!(parse!(b"\x1b]4;1;rgb:ff/80/0;2;#102030;3;?\x07"), vec![
+            call!(palette_set, Indexed(1), (0xff, 0x80, 0x00)),
+            call!(palette_set, Indexed(2), (0x10, 0x20, 0x30)),
+            call!(palette_query, Indexed(3)),
+        ]);
+        assert_eq!(parse!(b"\x1b]10;rgb:ffff/0000/8080;?\x1b\\"), vec![
+            call!(palette_set, Foreground, (0xff, 0x00, 0x80)),
+            call!(palette_query, Background),
+        ]);
+        assert_eq!(parse!(b"\x1b]11;?\x07\x1b]4;1;bogus\x07"), vec![ call!(palette_query, Background) ]);
+        assert_eq!(parse!(b"\x1b]104\x07\x1b]104;5;6\x07\x1b]111\x07"), vec![
+            call!(palette_reset, None::<VTPaletteSlot>),
+            call!(palette_reset, Some(Indexed(5))),
+            call!(palette_reset, Some(Indexed(6))),
+            call!(palette_reset, Some(Background)),
+        ]);
+    }
+
+    #[test]
+    fn osc_working_dir() {
+        assert_eq!(parse!(b"\x1b]7;file://box/home/user/My%20Files%2\x07"), vec![
+            call!(set_working_dir, "box", Path::new("/home/user/My Files%2")),
+        ]);
+        assert_eq!(parse!(b"\x1b]7;file:///tmp\x1b\\"), vec![ call!(set_working_dir, "", Path::new("/tmp")) ]);
+        assert_eq!(parse!(b"\x1b]7;http://box/tmp\x07\x1b]7;file://box\x07"), vec![]);
+    }
+
+    #[test]
+    fn osc_semantic_mark() {
+        use self::VTSemanticMark::*;
+
+        assert_eq!(parse!(b"\x1b]133;A;k=v\x07\x1b]133;B\x07\x1b]133;C\x07\x1b]133;D;1\x07\x1b]133;D\x07\x1b]133;X\x07"), vec![
+            call!(semantic_mark, PromptStart),
+            call!(semantic_mark, CommandStart),
+            call!(semantic_mark, OutputStart),
+            call!(semantic_mark, CommandFinished(Some(1))),
+            call!(semantic_mark, CommandFinished(None)),
+        ]);
+    }
+
+    #[test]
+    fn dcs() {
+        assert_eq!(parse!(b"\x1bP$qm\x1b\\"), vec![
+            call!(dcs_hook, 0, b'$', &[] as &[i32], b'q'),
+            call!(dcs_put, b'm'),
+            call!(dcs_unhook, true),
+        ]);
+        assert_eq!(parse!(b"\x1bP1;2\x07|a\x0a\x18b"), vec![
+            call!(dcs_hook, 0, 0, &[1, 2], b'|'),
+            call!(dcs_put, b'a'),
+            call!(dcs_put, b'\n'),
+            call!(dcs_unhook, false),
+            call!(put_char, 'b'),
+        ]);
+        assert_eq!(parse!(b"\x1bP1:2qdata\x1b\\\x1bP$ $qdata\x1b\\x"), vec![ call!(put_char, 'x') ]);
+    }
+
+    #[test]
+    fn apc_graphics() {
+        assert_eq!(parse!(b"\x1b_Ga=T,f=100;iVBO\r\nRw0K\x1b\\"), vec![ call!(kitty_graphics, b"a=T,f=100;iVBORw0K") ]);
+        assert_eq!(parse!(b"\x1b_Ga=T\x18x\x1b_Xa=T\x1b\\"), vec![ call!(put_char, 'x') ]);
+    }
+
+    #[test]
+    fn apc_tp() {
+        assert_eq!(parse!(b"\x1b_TPSetUserVar;name=host;value=box%3B1\x1b\\\x1b_TPSetUserVar;name=host\x1b\\"), vec![
+            call!(tp_set_user_var, "host", Some("box;1")),
+            call!(tp_set_user_var, "host", None as Option<&str>),
+        ]);
+        assert_eq!(parse!(b"\x1b_TPBookmark;label=build%20done\x1b\\\x1b_TPBookmark\x1b\\"), vec![
+            call!(tp_bookmark, "build done"),
+            call!(tp_bookmark, ""),
+        ]);
+        assert_eq!(parse!(b"\x1b_TPNewTab;cwd=/tmp\x1b\\\x1b_TPNewTab\x1b\\"), vec![
+            call!(tp_new_tab, Some(Path::new("/tmp"))),
+            call!(tp_new_tab, None as Option<&Path>),
+        ]);
+        assert_eq!(parse!(b"\x1b_TPFoo;a=b\x1b\\\x1b_TPSetUserVar;value=x\x1b\\\x1b_TPBookmark\x18x"), vec![ call!(put_char, 'x') ]);
+    }
+
     // TODO: more tests
 }